@@ -0,0 +1,98 @@
+use std::process::{
+    Command,
+    Stdio,
+};
+
+use data::{
+    BatStatus,
+    System,
+};
+use util::{
+    self,
+    Result,
+    Error,
+};
+
+/// Degrees, or percent, a value has to recover by before an alert can fire again, unless the
+/// config says otherwise.
+const HYSTERESIS: f64 = 2.0;
+
+/// What an alert watches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    /// Charge of the battery in percent, only while discharging.
+    Battery,
+    /// CPU temperature.
+    Temp,
+}
+
+/// Runs a command once a value crosses a threshold.
+///
+/// After firing, the alert is disarmed until the value has moved back past the threshold by the
+/// hysteresis, so a value hovering around the threshold doesn't fire it over and over.
+#[derive(Debug)]
+pub struct Alert {
+    source: Source,
+    threshold: f64,
+    /// Fire when the value rises above the threshold, rather than when it drops below it.
+    rising: bool,
+    hysteresis: f64,
+    cmd: String,
+    armed: bool,
+}
+
+impl Alert {
+    pub fn new(conf: &util::Alert) -> Result<Alert> {
+        let source = match &*conf.source {
+            "battery" => Source::Battery,
+            "temp" => Source::Temp,
+            other => return Err(Error::new(format!("unknown alert source `{}`", other))),
+        };
+        let (threshold, rising) = match (conf.below, conf.above) {
+            (Some(below), None) => (below, false),
+            (None, Some(above)) => (above, true),
+            _ => return Err(Error::new("an alert needs exactly one of `below` and `above`")),
+        };
+
+        Ok(Alert {
+            source: source,
+            threshold: threshold,
+            rising: rising,
+            hysteresis: conf.hysteresis.unwrap_or(HYSTERESIS),
+            cmd: conf.command.clone(),
+            armed: true,
+        })
+    }
+
+    /// Checks the watched value, returning the command to run if the alert fires.
+    pub fn check(&mut self, sys: &System) -> Option<Command> {
+        // Plugging in is what a low battery alert asks for, so don't nag after that.
+        let (value, active) = match self.source {
+            Source::Battery => (sys.bat.pct() as f64, sys.bat.status == BatStatus::Discharging),
+            Source::Temp => (sys.cpu.temp() as f64, true),
+        };
+
+        let (crossed, recovered) = if self.rising {
+            (value > self.threshold, value <= self.threshold - self.hysteresis)
+        } else {
+            (value < self.threshold, value >= self.threshold + self.hysteresis)
+        };
+        if recovered {
+            self.armed = true;
+        }
+        if !active || !crossed || !self.armed {
+            return None;
+        }
+        self.armed = false;
+
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c")
+           .arg(&self.cmd)
+           .env("BAR_VALUE", value.to_string())
+           .env("BAR_THRESHOLD", self.threshold.to_string())
+           .stdin(Stdio::null())
+           .stdout(Stdio::null())
+           .stderr(Stdio::inherit());
+        Some(cmd)
+    }
+}
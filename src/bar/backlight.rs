@@ -0,0 +1,400 @@
+use std::process::{
+    Command,
+    Stdio,
+};
+use std::collections::BTreeMap;
+use std::io::prelude::*;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::fs::{
+    self,
+    File,
+    OpenOptions,
+};
+use std::time::Duration;
+use std::char;
+use std::cmp;
+
+use rustc_serialize::json::{
+    Json,
+    ToJson,
+};
+
+use bar::{
+    Align,
+    Format,
+    Formatter,
+    Position,
+};
+use event::{
+    Event,
+    EventLoop,
+    Token,
+    Watch,
+};
+use util::{
+    self,
+    Result,
+    Error,
+};
+
+/// Where the kernel lists backlights.
+const BACKLIGHT_DIR: &'static str = "/sys/class/backlight";
+
+/// Percent each scroll adjusts the brightness by, unless the config says otherwise.
+const STEP: u32 = 5;
+
+/// Seconds between reads of the brightness. Changes made by the kernel, e.g. on a hotkey, don't
+/// show up as writes to the device.
+const POLL_INTERVAL: u64 = 1;
+
+/// A module showing the brightness of a backlight, adjusted by scrolling over it.
+#[derive(Debug)]
+pub struct Backlight {
+    name: String,
+    /// The device's directory, e.g. `/sys/class/backlight/intel_backlight`.
+    dir: PathBuf,
+    pos: Position,
+    /// Watches for writes to `brightness`, so changes made from userspace show right away.
+    src: Token,
+    timer: Token,
+    step: u32,
+    /// Adjusts the brightness instead of writing to the device, for when that needs privileges.
+    helper: Option<String>,
+    /// Icons from dim to bright.
+    icons: Vec<char>,
+    brightness: u64,
+    max: u64,
+}
+
+impl Backlight {
+    pub fn new(conf: &util::Backlight, ev: &mut EventLoop) -> Result<Backlight> {
+        let align: Align = try!(conf.align.parse());
+        let dir = match conf.device {
+            Some(ref device) => PathBuf::from(device),
+            None => try!(find_device(Path::new(BACKLIGHT_DIR))),
+        };
+        let src = try!(ev.add_watch(&dir.join("brightness"), Watch::File));
+        let timer = try!(ev.add_timer(Duration::from_secs(POLL_INTERVAL)));
+
+        let mut backlight = Backlight {
+            name: conf.name.clone(),
+            dir: dir,
+            pos: Position::new(align, conf.index),
+            src: src,
+            timer: timer,
+            step: conf.step.unwrap_or(STEP),
+            helper: conf.helper.clone(),
+            icons: conf.icons.as_ref().map_or(Vec::new(), |icons| {
+                icons.iter().filter_map(|&cp| char::from_u32(cp)).collect()
+            }),
+            brightness: 0,
+            max: 0,
+        };
+        try!(backlight.read());
+        Ok(backlight)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn position(&self) -> Position {
+        self.pos
+    }
+
+    /// Returns `true` if `event` came from one of this module's sources.
+    pub fn owns(&self, event: &Event) -> bool {
+        let token = event.token();
+        token == self.src || token == self.timer
+    }
+
+    /// Handles an event from one of this module's sources, returning `true` if the brightness
+    /// changed.
+    pub fn handle(&mut self, event: Event) -> Result<bool> {
+        match event {
+            Event::Changed(..) | Event::Timer(..) => self.read(),
+            _ => Ok(false),
+        }
+    }
+
+    /// Removes the module's sources from `ev`.
+    pub fn stop(&mut self, ev: &mut EventLoop) -> Result<()> {
+        try!(ev.remove(self.src));
+        ev.remove(self.timer)
+    }
+
+    /// Returns `true` if `action` is one `lemonbar` reports for scrolling over this module.
+    pub fn owns_action(&self, action: &str) -> bool {
+        action == self.action(true) || action == self.action(false)
+    }
+
+    /// Adjusts the brightness by one step, up or down depending on `action`.
+    ///
+    /// Returns the helper's command if there is one, since running it is up to the caller.
+    /// Otherwise the device is written to directly.
+    pub fn scroll(&mut self, action: &str) -> Result<Option<Command>> {
+        // Step in the device's own units, so one with few levels still changes every time.
+        let step = cmp::max(self.max * self.step as u64 / 100, 1);
+        let brightness = if action == self.action(true) {
+            self.brightness + step
+        } else {
+            self.brightness.saturating_sub(step)
+        };
+        // Stop short of turning the screen off altogether.
+        let brightness = cmp::min(cmp::max(brightness, 1), self.max);
+        let pct = percent(brightness, self.max);
+
+        if let Some(ref helper) = self.helper {
+            let mut cmd = Command::new("sh");
+            cmd.arg("-c")
+               .arg(helper)
+               .env("BAR_BRIGHTNESS", brightness.to_string())
+               .env("BAR_PCT", pct.to_string())
+               .stdin(Stdio::null())
+               .stdout(Stdio::null())
+               .stderr(Stdio::inherit());
+            return Ok(Some(cmd));
+        }
+
+        let mut file = try!(OpenOptions::new()
+                                .write(true)
+                                .truncate(true)
+                                .open(self.dir.join("brightness")));
+        try!(file.write_all(brightness.to_string().as_bytes()));
+        self.brightness = brightness;
+        Ok(None)
+    }
+
+    /// Brightness in percent of the maximum.
+    pub fn pct(&self) -> u64 {
+        percent(self.brightness, self.max)
+    }
+
+    fn action(&self, up: bool) -> String {
+        format!("backlight:{}:{}", self.name, if up { "up" } else { "down" })
+    }
+
+    /// Reads the brightness from the device, returning `true` if it changed.
+    ///
+    /// `actual_brightness` is what the hardware reports, which may differ from what was last
+    /// written to `brightness`.
+    fn read(&mut self) -> Result<bool> {
+        let brightness = try!(read_num(&self.dir.join("actual_brightness")));
+        let max = try!(read_num(&self.dir.join("max_brightness")));
+        let changed = brightness != self.brightness || max != self.max;
+        self.brightness = brightness;
+        self.max = max;
+        Ok(changed)
+    }
+}
+
+impl Format for Backlight {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<()> {
+        // Colons end the action, so any in the name have to be escaped.
+        try!(fmt.write(&*format!("%{{A4:{}:}}%{{A5:{}:}}",
+                                 self.action(true).replace(":", "\\:"),
+                                 self.action(false).replace(":", "\\:"))));
+        if !self.icons.is_empty() {
+            let level = cmp::min(self.pct() as usize * self.icons.len() / 100,
+                                 self.icons.len() - 1);
+            try!(fmt.write(&self.icons[level]));
+            try!(fmt.write(" "));
+        }
+        try!(fmt.write(&*format!("{}%%", self.pct())));
+        fmt.write("%{A}%{A}")
+    }
+}
+
+impl ToJson for Backlight {
+    fn to_json(&self) -> Json {
+        let mut obj = BTreeMap::new();
+        obj.insert(String::from("brightness"), self.brightness.to_json());
+        obj.insert(String::from("max"), self.max.to_json());
+        obj.insert(String::from("pct"), self.pct().to_json());
+        Json::Object(obj)
+    }
+}
+
+/// Returns `brightness` in percent of `max`, rounded to the nearest.
+fn percent(brightness: u64, max: u64) -> u64 {
+    if max == 0 {
+        0
+    } else {
+        (brightness * 100 + max / 2) / max
+    }
+}
+
+/// Returns the backlight in `dir` most likely to control the screen.
+///
+/// Firmware interfaces are preferred over platform ones, and those over raw access to the
+/// hardware, as the kernel recommends. Ties go to the first by name.
+fn find_device(dir: &Path) -> Result<PathBuf> {
+    let mut devices: Vec<(u32, PathBuf)> = try!(fs::read_dir(dir))
+        .filter_map(|e| e.ok())
+        .map(|e| {
+            let path = e.path();
+            (rank(&path), path)
+        })
+        .collect();
+    devices.sort();
+    devices.into_iter()
+        .next()
+        .map(|(_, path)| path)
+        .ok_or(Error::new(format!("no backlight in `{}`", dir.display())))
+}
+
+/// Ranks a backlight by its `type`, lowest first.
+fn rank(dev: &Path) -> u32 {
+    let mut kind = String::new();
+    let _ = File::open(dev.join("type")).and_then(|mut f| f.read_to_string(&mut kind));
+    match kind.trim() {
+        "firmware" => 0,
+        "platform" => 1,
+        "raw" => 2,
+        _ => 3,
+    }
+}
+
+fn read_num(path: &Path) -> Result<u64> {
+    let mut buf = String::new();
+    try!(File::open(path).and_then(|mut f| f.read_to_string(&mut buf)));
+    buf.trim().parse().map_err(|_| {
+        Error::new(format!("`{}` does not hold a number", path.display()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{
+        self,
+        File,
+    };
+    use std::io::prelude::*;
+    use std::path::{
+        Path,
+        PathBuf,
+    };
+
+    use event::{
+        Event,
+        EventLoop,
+    };
+    use util;
+    use util::testing::TempPath;
+    use super::{
+        find_device,
+        read_num,
+        Backlight,
+    };
+
+    /// Adds a device to a fake `/sys/class/backlight`.
+    fn device(sysfs: &Path, name: &str, kind: &str, brightness: u64, max: u64) -> PathBuf {
+        let dev = sysfs.join(name);
+        fs::create_dir_all(&dev).unwrap();
+        File::create(dev.join("type")).unwrap().write_all(kind.as_bytes()).unwrap();
+        write(&dev.join("brightness"), brightness);
+        write(&dev.join("actual_brightness"), brightness);
+        write(&dev.join("max_brightness"), max);
+        dev
+    }
+
+    fn write(path: &Path, n: u64) {
+        let mut file = File::create(path).unwrap();
+        writeln!(file, "{}", n).unwrap();
+    }
+
+    fn backlight(dev: &Path, step: Option<u32>, ev: &mut EventLoop) -> Backlight {
+        let conf = util::Backlight {
+            name: String::from("lcd"),
+            align: String::from("right"),
+            index: 0,
+            device: Some(dev.to_string_lossy().into_owned()),
+            step: step,
+            helper: None,
+            icons: None,
+        };
+        Backlight::new(&conf, ev).unwrap()
+    }
+
+    #[test]
+    fn find() {
+        let sysfs = TempPath::new("backlight-find");
+        fs::create_dir_all(sysfs.path()).unwrap();
+        assert!(find_device(sysfs.path()).is_err());
+
+        fs::create_dir_all(sysfs.path().join("a_untyped")).unwrap();
+        let raw = device(sysfs.path(), "intel_backlight", "raw", 1, 10);
+        assert_eq!(find_device(sysfs.path()).unwrap(), raw);
+        let platform = device(sysfs.path(), "thinkpad_screen", "platform", 1, 10);
+        assert_eq!(find_device(sysfs.path()).unwrap(), platform);
+        let firmware = device(sysfs.path(), "acpi_video1", "firmware\n", 1, 10);
+        device(sysfs.path(), "acpi_video2", "firmware", 1, 10);
+        assert_eq!(find_device(sysfs.path()).unwrap(), firmware);
+    }
+
+    #[test]
+    fn read() {
+        let sysfs = TempPath::new("backlight-read");
+        let dev = device(sysfs.path(), "intel_backlight", "raw", 468, 937);
+        let mut ev = EventLoop::new().unwrap();
+        let mut lcd = backlight(&dev, None, &mut ev);
+        assert_eq!((lcd.brightness, lcd.max), (468, 937));
+        assert_eq!(lcd.pct(), 50);
+        assert!(!lcd.read().unwrap());
+
+        // Changes made by the kernel only show in `actual_brightness`, and are polled for.
+        write(&dev.join("actual_brightness"), 937);
+        let timer = lcd.timer;
+        assert!(lcd.owns(&Event::Timer(timer)));
+        assert!(lcd.handle(Event::Timer(timer)).unwrap());
+        assert_eq!(lcd.pct(), 100);
+        assert!(!lcd.handle(Event::Timer(timer)).unwrap());
+
+        File::create(dev.join("actual_brightness")).unwrap().write_all(b"bright\n").unwrap();
+        assert!(lcd.read().is_err());
+        assert!(read_num(&dev.join("missing")).is_err());
+    }
+
+    #[test]
+    fn scroll() {
+        let sysfs = TempPath::new("backlight-scroll");
+        let dev = device(sysfs.path(), "intel_backlight", "raw", 500, 1000);
+        let mut ev = EventLoop::new().unwrap();
+        let mut lcd = backlight(&dev, Some(10), &mut ev);
+
+        assert!(lcd.scroll("backlight:lcd:up").unwrap().is_none());
+        assert_eq!(read_num(&dev.join("brightness")).unwrap(), 600);
+        assert_eq!(lcd.pct(), 60);
+
+        lcd.brightness = 950;
+        lcd.scroll("backlight:lcd:up").unwrap();
+        assert_eq!(lcd.brightness, 1000);
+
+        // Scrolling down stops short of turning the screen off.
+        lcd.brightness = 50;
+        lcd.scroll("backlight:lcd:down").unwrap();
+        assert_eq!(lcd.brightness, 1);
+        lcd.scroll("backlight:lcd:down").unwrap();
+        assert_eq!(read_num(&dev.join("brightness")).unwrap(), 1);
+    }
+
+    #[test]
+    fn scroll_few_levels() {
+        let sysfs = TempPath::new("backlight-few");
+        let dev = device(sysfs.path(), "acpi_video0", "firmware", 3, 7);
+        let mut ev = EventLoop::new().unwrap();
+        let mut lcd = backlight(&dev, None, &mut ev);
+
+        // 5% of 7 levels rounds down to nothing, but every scroll still moves one level.
+        lcd.scroll("backlight:lcd:up").unwrap();
+        assert_eq!(lcd.brightness, 4);
+        lcd.scroll("backlight:lcd:down").unwrap();
+        lcd.scroll("backlight:lcd:down").unwrap();
+        assert_eq!(lcd.brightness, 2);
+        assert_eq!(read_num(&dev.join("brightness")).unwrap(), 2);
+        assert_eq!(lcd.pct(), 29);
+    }
+}
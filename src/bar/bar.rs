@@ -1,9 +1,15 @@
 use std::char;
 use std::str;
+use std::str::FromStr;
 use std::fmt;
-use std::cmp::Ordering;
-use std::collections::BTreeMap;
-use std::collections::Bound;
+use std::cmp::{
+    self,
+    Ordering,
+};
+use std::collections::{
+    BTreeMap,
+    BTreeSet,
+};
 use std::process::{
     Command,
     Stdio,
@@ -14,37 +20,66 @@ use std::io::{
     BufWriter,
 };
 
-use pipe::PipeWriter;
+use unicode_segmentation::UnicodeSegmentation;
+
 use util::{
+    text,
     Result,
     Error,
 };
 
+/// Modules narrower than this, in columns, are hidden rather than shortened any further.
+const MIN_COLUMNS: usize = 4;
+
+/// Powerline arrows, pointing right and left.
+const ARROW_RIGHT: char = '\u{e0b0}';
+const ARROW_LEFT: char = '\u{e0b2}';
+
 pub struct Bar {
-    stdin: BufWriter<ChildStdin>,
+    stdin: Option<BufWriter<ChildStdin>>,
     elts: BTreeMap<Position, Vec<u8>>,
+    stale: BTreeSet<Position>,
+    hidden: BTreeSet<Position>,
+    priorities: BTreeMap<Position, i32>,
+    layout: Option<Layout>,
+    decorations: Decorations,
+    visible: bool,
+    /// The last frame written, so identical ones can be skipped.
+    last: Option<Vec<u8>>,
 }
 
 impl Bar {
-    pub fn new(output: &PipeWriter, args: &[String]) -> Result<Bar> {
-        let outpipe = try!(output.stdio());
-        let bar = try!(Command::new("lemonbar")
-                       .args(args)
-                       .stdin(Stdio::piped())
-                       .stdout(outpipe)
-                       .stderr(Stdio::inherit())
-                       .spawn()
-                       .map_err(|err| Error::from(err)));
-
-        let stdin = try!(bar.stdin.ok_or(Error::new("failed to grab `lemonbar` stdin")));
-
-        Ok(Bar {
-            stdin: BufWriter::new(stdin),
+    pub fn new() -> Bar {
+        Bar {
+            stdin: None,
             elts: BTreeMap::new(),
-        })
+            stale: BTreeSet::new(),
+            hidden: BTreeSet::new(),
+            priorities: BTreeMap::new(),
+            layout: None,
+            decorations: Decorations::default(),
+            visible: true,
+            last: None,
+        }
     }
 
-    pub fn register<T>(&mut self, pos: Position, elt: &T)
+    /// Returns the command that runs `lemonbar` with `args`.
+    pub fn command(args: &[String]) -> Command {
+        let mut cmd = Command::new("lemonbar");
+        cmd.args(args)
+           .stdin(Stdio::piped())
+           .stderr(Stdio::inherit());
+        cmd
+    }
+
+    /// Sets the `lemonbar` instance frames are written to.
+    pub fn set_output(&mut self, stdin: ChildStdin) {
+        self.stdin = Some(BufWriter::new(stdin));
+        // A new instance starts out blank.
+        self.last = None;
+    }
+
+    pub fn register<T: ?Sized>(&mut self, pos: Position, elt: &T)
         where T: Format
     {
         let mut buf = Vec::new();
@@ -59,31 +94,486 @@ impl Bar {
         self.elts.remove(&pos);
     }
 
-    pub fn flush(&mut self) -> Result<()> {
-        // Left.
-        try!(self.stdin.write_all(b"%{l}"));
-        for (_, elt) in self.elts.range(Bound::Unbounded, Bound::Excluded(&Position::center())) {
-            try!(self.stdin.write_all(elt));
+    /// Marks the element at `pos` as stale, i.e. its source has stopped updating it.
+    pub fn set_stale(&mut self, pos: Position, stale: bool) {
+        if stale {
+            self.stale.insert(pos);
+        } else {
+            self.stale.remove(&pos);
         }
+    }
 
-        // Center.
-        try!(self.stdin.write_all(b"%{c}"));
-        for (_, elt) in self.elts.range(Bound::Included(&Position::center()),
-                                        Bound::Excluded(&Position::right()))
-        {
-            try!(self.stdin.write_all(elt));
+    pub fn is_stale(&self, pos: Position) -> bool {
+        self.stale.contains(&pos)
+    }
+
+    /// Returns the last text registered at `pos`, formatting tags included.
+    pub fn rendered(&self, pos: Position) -> Option<String> {
+        self.elts.get(&pos).map(|buf| String::from_utf8_lossy(buf).into_owned())
+    }
+
+    /// Hides or shows the element at `pos`, without forgetting it.
+    pub fn set_hidden(&mut self, pos: Position, hidden: bool) {
+        if hidden {
+            self.hidden.insert(pos);
+        } else {
+            self.hidden.remove(&pos);
         }
+    }
+
+    pub fn is_hidden(&self, pos: Position) -> bool {
+        self.hidden.contains(&pos)
+    }
+
+    /// Sets how reluctantly the element at `pos` is shortened or hidden when the bar is too
+    /// narrow for everything. Elements default to a priority of 0.
+    pub fn set_priority(&mut self, pos: Position, priority: i32) {
+        self.priorities.insert(pos, priority);
+    }
+
+    /// Clears every priority set with `set_priority`.
+    pub fn clear_priorities(&mut self) {
+        self.priorities.clear();
+    }
+
+    /// Sets the room the bar has. Without a layout elements are never shortened or hidden.
+    pub fn set_layout(&mut self, layout: Option<Layout>) {
+        self.layout = layout;
+    }
+
+    /// Sets what is drawn between elements and around sections.
+    pub fn set_decorations(&mut self, decorations: Decorations) {
+        self.decorations = decorations;
+    }
+
+    /// Hides or shows the whole bar.
+    pub fn set_visible(&mut self, visible: bool) {
+        self.visible = visible;
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
 
-        // Right.
-        try!(self.stdin.write_all(b"%{r}"));
-        for (_, elt) in self.elts.range(Bound::Included(&Position::right()), Bound::Unbounded) {
-            try!(self.stdin.write_all(elt));
+    /// Writes the current frame, unless it is identical to the last one written.
+    pub fn flush(&mut self) -> Result<()> {
+        let frame = if self.visible { self.frame() } else { b"\n".to_vec() };
+        if self.last.as_ref() == Some(&frame) {
+            return Ok(());
         }
+        self.write_frame(frame)
+    }
+
+    /// Forgets the last frame written, so the next `flush` writes one even if nothing changed.
+    pub fn invalidate(&mut self) {
+        self.last = None;
+    }
 
-        try!(self.stdin.write_all(b"\n"));
-        try!(self.stdin.flush());
+    /// Writes an empty frame, blanking the bar.
+    pub fn clear(&mut self) -> Result<()> {
+        self.write_frame(b"\n".to_vec())
+    }
+
+    fn write_frame(&mut self, frame: Vec<u8>) -> Result<()> {
+        let res = match self.stdin {
+            Some(ref mut stdin) => stdin.write_all(&frame).and_then(|_| stdin.flush()),
+            None => return Ok(()),
+        };
+        if let Err(err) = res {
+            // `lemonbar` is gone; stop writing until a new instance is attached.
+            self.stdin = None;
+            self.last = None;
+            return Err(Error::from(err));
+        }
+        self.last = Some(frame);
         Ok(())
     }
+
+    fn frame(&self) -> Vec<u8> {
+        let fitted = self.fit();
+        let mut frame = Vec::new();
+
+        for &align in &[Align::Left, Align::Center, Align::Right] {
+            frame.extend_from_slice(match align {
+                Align::Left => b"%{l}",
+                Align::Center => b"%{c}",
+                Align::Right => b"%{r}",
+            });
+
+            let elts: Vec<(&Position, &[u8])> = self.elts.iter()
+                .filter(|&(pos, _)| pos.align() == align)
+                .map(|(pos, elt)| (pos, &**fitted.get(pos).unwrap_or(elt)))
+                .filter(|&(pos, elt)| !self.hidden.contains(pos) && !elt.is_empty())
+                .collect();
+            if elts.is_empty() {
+                continue;
+            }
+
+            let ends = self.decorations.ends(align);
+            frame.extend_from_slice(ends.start.as_bytes());
+            for (i, &(pos, elt)) in elts.iter().enumerate() {
+                if i > 0 {
+                    self.write_separator(&mut frame, align, elts[i - 1].1, elt);
+                }
+                self.write_elt(&mut frame, pos, elt);
+            }
+            frame.extend_from_slice(ends.end.as_bytes());
+        }
+
+        frame.push(b'\n');
+        frame
+    }
+
+    /// Writes the separator between the adjacent elements `prev` and `next`.
+    fn write_separator(&self, frame: &mut Vec<u8>, align: Align, prev: &[u8], next: &[u8]) {
+        match self.decorations.separator {
+            Separator::None => {},
+            Separator::Text(ref sep) => frame.extend_from_slice(sep.as_bytes()),
+            Separator::Arrows => {
+                let (prev, next) = (String::from_utf8_lossy(prev), String::from_utf8_lossy(next));
+                let (prev, next) = (background(&prev, true), background(&next, false));
+                // The arrow is drawn in the color of the element it points away from, on top of
+                // the one it points at.
+                let (fg, bg, arrow) = if align == Align::Right {
+                    (next, prev, ARROW_LEFT)
+                } else {
+                    (prev, next, ARROW_RIGHT)
+                };
+                let fg = if fg == "-" { &*self.decorations.background } else { fg };
+                frame.extend_from_slice(format!("%{{F{} B{}}}{}%{{F- B-}}", fg, bg, arrow)
+                                        .as_bytes());
+            },
+        }
+    }
+
+    fn write_elt(&self, frame: &mut Vec<u8>, pos: &Position, elt: &[u8]) {
+        if self.hidden.contains(pos) || elt.is_empty() {
+            return;
+        }
+        if self.stale.contains(pos) {
+            // Overline stale elements in red.
+            frame.extend_from_slice(format!("%{{U#{:x}}}%{{+o}}", Color::Red as u32).as_bytes());
+            frame.extend_from_slice(elt);
+            frame.extend_from_slice(b"%{-o}%{U-}");
+        } else {
+            frame.extend_from_slice(elt);
+        }
+    }
+
+    /// Shortens or hides the lowest priority elements until the sections no longer overlap,
+    /// returning the text of those that changed. Hidden elements are left empty.
+    fn fit(&self) -> BTreeMap<Position, Vec<u8>> {
+        let mut fitted = BTreeMap::new();
+        let layout = match self.layout {
+            Some(ref layout) => layout,
+            None => return fitted,
+        };
+
+        let mut elts: Vec<(Position, String, u32)> = self.elts.iter()
+            .filter(|&(pos, _)| !self.hidden.contains(pos))
+            .map(|(&pos, buf)| {
+                let elt = String::from_utf8_lossy(buf).into_owned();
+                let width = layout.width_of(&elt);
+                (pos, elt, width)
+            })
+            .collect();
+        // Lowest priority first, and right to left within a priority.
+        elts.sort_by(|a, b| {
+            let (pa, pb) = (self.priority(a.0), self.priority(b.0));
+            match pa.cmp(&pb) {
+                Ordering::Equal => b.0.cmp(&a.0),
+                ord => ord,
+            }
+        });
+
+        let mut widths = [0u32; 3];
+        let mut counts = [0usize; 3];
+        for &(pos, ref elt, width) in &elts {
+            widths[pos.align() as usize] += width;
+            if !elt.is_empty() {
+                counts[pos.align() as usize] += 1;
+            }
+        }
+
+        for &(pos, ref elt, width) in &elts {
+            if self.fits(layout, widths, counts) {
+                break;
+            }
+            if elt.is_empty() {
+                continue;
+            }
+            let section = pos.align() as usize;
+            widths[section] -= width;
+            counts[section] -= 1;
+
+            // Keep as much of the element as there is room for.
+            let columns = text::width(&strip(elt));
+            let shortened = (MIN_COLUMNS..columns).rev()
+                .map(|max| shorten(elt, max))
+                .map(|short| {
+                    let width = layout.width_of(&short);
+                    (short, width)
+                })
+                .find(|&(_, width)| {
+                    let (mut with, mut count) = (widths, counts);
+                    with[section] += width;
+                    count[section] += 1;
+                    self.fits(layout, with, count)
+                });
+            match shortened {
+                Some((short, width)) => {
+                    widths[section] += width;
+                    counts[section] += 1;
+                    fitted.insert(pos, short.into_bytes());
+                },
+                None => {
+                    fitted.insert(pos, Vec::new());
+                },
+            }
+        }
+        fitted
+    }
+
+    /// Returns `true` if sections with elements of the given widths, and the given number of
+    /// elements, fit once decorated.
+    fn fits(&self, layout: &Layout, widths: [u32; 3], counts: [usize; 3]) -> bool {
+        let mut decorated = widths;
+        for (i, &align) in [Align::Left, Align::Center, Align::Right].iter().enumerate() {
+            if counts[i] == 0 {
+                continue;
+            }
+            let ends = self.decorations.ends(align);
+            let sep = match self.decorations.separator {
+                Separator::None => 0,
+                Separator::Text(ref sep) => layout.width_of(sep),
+                Separator::Arrows => layout.width_of(&ARROW_RIGHT.to_string()),
+            };
+            decorated[i] += layout.width_of(&ends.start) + layout.width_of(&ends.end) +
+                            sep * (counts[i] as u32 - 1);
+        }
+        layout.fits(decorated)
+    }
+
+    fn priority(&self, pos: Position) -> i32 {
+        self.priorities.get(&pos).cloned().unwrap_or(0)
+    }
+}
+
+/// The room the bar has, used to estimate how wide elements are drawn.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layout {
+    /// Width of the bar, in pixels.
+    pub width: u32,
+    /// Width of a column in each font, in pixels, in the order the fonts are passed to
+    /// `lemonbar`.
+    pub char_widths: Vec<u32>,
+}
+
+impl Layout {
+    /// Estimates the width of formatted text, in pixels.
+    ///
+    /// Text is assumed to be drawn in the font selected with `%{T...}`, or the first one.
+    fn width_of(&self, elt: &str) -> u32 {
+        let default = self.char_widths.first().cloned().unwrap_or(0);
+        let mut char_width = default;
+        let mut width = 0;
+        for seg in segments(elt) {
+            match seg {
+                Segment::Tag(tag) => {
+                    for attr in tag.split_whitespace() {
+                        if attr.starts_with('T') {
+                            char_width = attr[1..].parse::<usize>().ok()
+                                .and_then(|i| i.checked_sub(1))
+                                .and_then(|i| self.char_widths.get(i).cloned())
+                                .unwrap_or(default);
+                        }
+                    }
+                },
+                Segment::Text(_, shown) => width += text::width(shown) as u32 * char_width,
+            }
+        }
+        width
+    }
+
+    /// Returns `true` if sections of the given widths don't overlap. The center section is
+    /// centered on the bar, so it only fits if there is room for it on both sides.
+    fn fits(&self, widths: [u32; 3]) -> bool {
+        let (left, center, right) = (widths[0], widths[1], widths[2]);
+        if center == 0 {
+            left + right <= self.width
+        } else {
+            2 * cmp::max(left, right) + center <= self.width
+        }
+    }
+}
+
+/// What is drawn between the elements of a section.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Separator {
+    None,
+    /// Formatted text, e.g. ` | ` or an icon.
+    Text(String),
+    /// Powerline-style arrows, colored after the backgrounds of the elements on either side.
+    Arrows,
+}
+
+/// Formatted text written at the start and end of a section, if it has any elements.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Ends {
+    pub start: String,
+    pub end: String,
+}
+
+/// Decorations drawn between elements and around sections.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decorations {
+    pub separator: Separator,
+    /// Background of the bar, which arrows next to elements without a background of their own
+    /// are drawn in.
+    pub background: String,
+    pub left: Ends,
+    pub center: Ends,
+    pub right: Ends,
+}
+
+impl Decorations {
+    fn ends(&self, align: Align) -> &Ends {
+        match align {
+            Align::Left => &self.left,
+            Align::Center => &self.center,
+            Align::Right => &self.right,
+        }
+    }
+}
+
+impl Default for Decorations {
+    fn default() -> Decorations {
+        Decorations {
+            separator: Separator::None,
+            background: String::from("-"),
+            left: Ends::default(),
+            center: Ends::default(),
+            right: Ends::default(),
+        }
+    }
+}
+
+/// Returns the background formatted text is drawn on at its first visible character, or its
+/// last if `last` is set. `-` stands for the default background.
+fn background(elt: &str, last: bool) -> &str {
+    let mut bg = "-";
+    let mut found = "-";
+    for seg in segments(elt) {
+        match seg {
+            Segment::Tag(tag) => {
+                for attr in tag.split_whitespace() {
+                    if attr.starts_with('B') {
+                        bg = &attr[1..];
+                    }
+                }
+            },
+            Segment::Text(..) => {
+                found = bg;
+                if !last {
+                    break;
+                }
+            },
+        }
+    }
+    found
+}
+
+/// A piece of formatted text.
+enum Segment<'a> {
+    /// The inside of a `%{...}` block.
+    Tag(&'a str),
+    /// A grapheme, as written and as shown.
+    Text(&'a str, &'a str),
+}
+
+/// Splits formatted text into tags and graphemes.
+fn segments(elt: &str) -> Vec<Segment> {
+    let mut segs = Vec::new();
+    let mut rest = elt;
+    while !rest.is_empty() {
+        if rest.starts_with("%{") {
+            match tag_end(&rest[2..]) {
+                Some(end) => {
+                    segs.push(Segment::Tag(&rest[2..end + 2]));
+                    rest = &rest[end + 3..];
+                },
+                None => {
+                    segs.push(Segment::Tag(&rest[2..]));
+                    rest = "";
+                },
+            }
+        } else if rest.starts_with("%%") {
+            segs.push(Segment::Text(&rest[..2], &rest[1..2]));
+            rest = &rest[2..];
+        } else {
+            let g = rest.graphemes(true).next().unwrap_or(rest);
+            segs.push(Segment::Text(g, g));
+            rest = &rest[g.len()..];
+        }
+    }
+    segs
+}
+
+/// Finds the `}` ending a block, skipping over the commands of clickable areas, which end at
+/// an unescaped `:`.
+fn tag_end(tag: &str) -> Option<usize> {
+    let mut in_cmd = false;
+    let mut prev = '\0';
+    for (i, c) in tag.char_indices() {
+        match c {
+            ':' if !in_cmd => in_cmd = true,
+            ':' if prev != '\\' => in_cmd = false,
+            '}' if !in_cmd => return Some(i),
+            _ => {},
+        }
+        prev = c;
+    }
+    None
+}
+
+/// Returns formatted text without its tags, unescaped.
+fn strip(elt: &str) -> String {
+    segments(elt).into_iter().filter_map(|seg| {
+        match seg {
+            Segment::Text(_, shown) => Some(shown),
+            Segment::Tag(..) => None,
+        }
+    }).collect()
+}
+
+/// Shortens formatted text to at most `max` columns, ending it with an ellipsis. Tags are
+/// kept, so colors and clickable areas are still closed.
+fn shorten(elt: &str, max: usize) -> String {
+    let mut out = String::new();
+    let mut used = 0;
+    let mut cut = false;
+    for seg in segments(elt) {
+        match seg {
+            Segment::Tag(tag) => {
+                out.push_str("%{");
+                out.push_str(tag);
+                out.push('}');
+            },
+            Segment::Text(..) if cut => {},
+            Segment::Text(written, shown) => {
+                let w = text::width(shown);
+                // Leave room for the ellipsis.
+                if used + w + 1 > max {
+                    out.push(text::ELLIPSIS);
+                    cut = true;
+                } else {
+                    out.push_str(written);
+                    used += w;
+                }
+            },
+        }
+    }
+    out
 }
 
 pub trait Format {
@@ -97,6 +587,20 @@ impl Format for str {
     }
 }
 
+impl<'a, T: ?Sized> Format for &'a T
+    where T: Format
+{
+    fn fmt(&self, fmt: &mut Formatter) -> Result<()> {
+        (**self).fmt(fmt)
+    }
+}
+
+impl Format for String {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<()> {
+        (**self).fmt(fmt)
+    }
+}
+
 impl Format for char {
     fn fmt(&self, fmt: &mut Formatter) -> Result<()> {
         let mut utf8 = [0u8; 4];
@@ -210,6 +714,29 @@ pub enum Align {
     Right,
 }
 
+impl FromStr for Align {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Align> {
+        match s {
+            "left" => Ok(Align::Left),
+            "center" => Ok(Align::Center),
+            "right" => Ok(Align::Right),
+            _ => Err(Error::new(format!("invalid alignment `{}`", s))),
+        }
+    }
+}
+
+impl fmt::Display for Align {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str(match *self {
+            Align::Left => "left",
+            Align::Center => "center",
+            Align::Right => "right",
+        })
+    }
+}
+
 impl Format for Align {
     fn fmt(&self, fmt: &mut Formatter) -> Result<()> {
         let align = match *self {
@@ -239,6 +766,14 @@ impl<T> Styled<T>
         self.bg = bg;
         self
     }
+
+    /// Sets both colors from `style`.
+    pub fn set_style(&mut self, style: Style) -> &mut Self {
+        let (fg, bg) = style.colors();
+        self.fg = fg;
+        self.bg = bg;
+        self
+    }
 }
 
 impl<T> From<T> for Styled<T>
@@ -267,6 +802,171 @@ impl<T> Format for Styled<T>
     }
 }
 
+/// Scrolls text wider than a number of columns, one grapheme per step.
+///
+/// Scrolling pauses at either end, then starts over. Formatting tags are kept, so only the text
+/// moves.
+#[derive(Debug)]
+pub struct Marquee<T> {
+    inner: T,
+    width: usize,
+    pause: u32,
+    offset: usize,
+    wait: u32,
+}
+
+impl<T> Marquee<T>
+    where T: Format
+{
+    /// Creates a marquee `width` columns wide, which waits `pause` steps at either end.
+    pub fn new(inner: T, width: usize, pause: u32) -> Marquee<T> {
+        Marquee {
+            inner: inner,
+            width: width,
+            pause: pause,
+            offset: 0,
+            wait: pause,
+        }
+    }
+
+    pub fn get(&self) -> &T {
+        &self.inner
+    }
+
+    /// Replaces the text, scrolling back to its start.
+    pub fn set(&mut self, inner: T) {
+        self.inner = inner;
+        self.offset = 0;
+        self.wait = self.pause;
+    }
+
+    /// Scrolls one step, returning `true` if what is shown changed.
+    pub fn step(&mut self) -> bool {
+        let max = max_offset(&render(&self.inner), self.width);
+        if max == 0 {
+            return false;
+        }
+        if self.wait > 0 {
+            self.wait -= 1;
+            return false;
+        }
+
+        if self.offset >= max {
+            self.offset = 0;
+        } else {
+            self.offset += 1;
+        }
+        if self.offset == 0 || self.offset == max {
+            self.wait = self.pause;
+        }
+        true
+    }
+}
+
+impl<T> Format for Marquee<T>
+    where T: Format
+{
+    fn fmt(&self, fmt: &mut Formatter) -> Result<()> {
+        let elt = render(&self.inner);
+        // The text may have shrunk since the last step.
+        let offset = cmp::min(self.offset, max_offset(&elt, self.width));
+        fmt.write(&*window(&elt, offset, self.width))
+    }
+}
+
+/// Formats `elt` into a string.
+fn render<T: ?Sized>(elt: &T) -> String
+    where T: Format
+{
+    let mut buf = Vec::new();
+    {
+        let mut fmtr = Formatter { buf: &mut buf };
+        let _ = elt.fmt(&mut fmtr);
+    }
+    String::from_utf8_lossy(&buf).into_owned()
+}
+
+/// Returns the number of graphemes formatted text has to be scrolled by for its end to show in
+/// `width` columns.
+fn max_offset(elt: &str, width: usize) -> usize {
+    let widths: Vec<usize> = segments(elt).into_iter().filter_map(|seg| {
+        match seg {
+            Segment::Text(_, shown) => Some(text::width(shown)),
+            Segment::Tag(..) => None,
+        }
+    }).collect();
+
+    let mut rest = widths.iter().fold(0, |sum, &w| sum + w);
+    let mut offset = 0;
+    while rest > width && offset < widths.len() {
+        rest -= widths[offset];
+        offset += 1;
+    }
+    offset
+}
+
+/// Returns at most `width` columns of formatted text, starting `offset` graphemes in. Tags are
+/// kept, so colors and clickable areas still apply.
+fn window(elt: &str, offset: usize, width: usize) -> String {
+    let mut out = String::new();
+    let mut skipped = 0;
+    let mut used = 0;
+    let mut full = false;
+    for seg in segments(elt) {
+        match seg {
+            Segment::Tag(tag) => {
+                out.push_str("%{");
+                out.push_str(tag);
+                out.push('}');
+            },
+            Segment::Text(..) if skipped < offset => skipped += 1,
+            Segment::Text(..) if full => {},
+            Segment::Text(written, shown) => {
+                let w = text::width(shown);
+                if used + w > width {
+                    full = true;
+                } else {
+                    out.push_str(written);
+                    used += w;
+                }
+            },
+        }
+    }
+    out
+}
+
+/// How urgently an element wants attention.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    Normal,
+    Warning,
+    Critical,
+}
+
+impl Style {
+    /// The foreground and background colors of the style.
+    pub fn colors(&self) -> (Color, Color) {
+        match *self {
+            Style::Normal => (Color::Default, Color::Default),
+            Style::Warning => (Color::Yellow, Color::Default),
+            Style::Critical => (Color::Base3, Color::Red),
+        }
+    }
+}
+
+impl FromStr for Style {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Style> {
+        match s {
+            "normal" => Ok(Style::Normal),
+            "warning" => Ok(Style::Warning),
+            "critical" => Ok(Style::Critical),
+            _ => Err(Error::new(format!("invalid style `{}`", s))),
+        }
+    }
+}
+
 #[allow(dead_code)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Color {
@@ -294,7 +994,7 @@ impl fmt::Display for Color {
         if *self == Color::Default {
             fmt.write_str("-")
         } else {
-            fmt.write_str(&format!("{:x}", *self as u32))
+            write!(fmt, "#{:06x}", *self as u32)
         }
     }
 }
@@ -318,3 +1018,111 @@ impl Format for Icon {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Align,
+        Bar,
+        Color,
+        Layout,
+        Position,
+        Style,
+        Styled,
+        render,
+        shorten,
+        strip,
+        tag_end,
+    };
+
+    fn layout(width: u32) -> Layout {
+        Layout {
+            width: width,
+            char_widths: vec![10, 6],
+        }
+    }
+
+    fn fitted(bar: &Bar, pos: Position) -> Option<String> {
+        bar.fit().get(&pos).map(|buf| String::from_utf8(buf.clone()).unwrap())
+    }
+
+    #[test]
+    fn escaped_percent() {
+        assert_eq!(strip("100%% full"), "100% full");
+        assert_eq!(strip("%%%{F#fff}%%"), "%%");
+        assert_eq!(layout(0).width_of("100%%"), 40);
+        // An escaped `%` is never split from its escape.
+        assert_eq!(shorten("100%% full", 5), "100%%\u{2026}");
+        assert_eq!(shorten("10%%", 3), "10\u{2026}");
+    }
+
+    #[test]
+    fn clickable_areas() {
+        assert_eq!(tag_end("F#fff}x"), Some(5));
+        assert_eq!(tag_end("A:echo }:}x"), Some(9));
+        assert_eq!(tag_end("A3:echo a\\:}:}x"), Some(13));
+        assert_eq!(tag_end("A:echo }"), None);
+
+        let elt = "%{A:notify-send }:}click%{A}";
+        assert_eq!(strip(elt), "click");
+        assert_eq!(layout(0).width_of(elt), 50);
+        assert_eq!(shorten(elt, 4), "%{A:notify-send }:}cli\u{2026}%{A}");
+    }
+
+    #[test]
+    fn wide_characters() {
+        assert_eq!(layout(0).width_of("日本語"), 60);
+        assert_eq!(layout(0).width_of("a%{T2}日本"), 34);
+        assert_eq!(shorten("日本語テキスト", 5), "日本\u{2026}");
+        assert_eq!(shorten("日本語テキスト", 6), "日本\u{2026}");
+        assert_eq!(shorten("日本語", 7), "日本語");
+    }
+
+    #[test]
+    fn styled() {
+        let mut styled = Styled::from("down");
+        assert_eq!(render(&styled), "%{F- B-}down%{F- B-}");
+        styled.set_style(Style::Critical);
+        assert_eq!(render(&styled), "%{F#fdf6e3 B#dc322f}down%{F- B-}");
+        // Colors are always written with all six digits.
+        styled.set_fg(Color::Base03);
+        assert_eq!(render(&styled), "%{F#002b36 B#dc322f}down%{F- B-}");
+    }
+
+    #[test]
+    fn overflow_order() {
+        let (left, right) = (Position::left(), Position::right());
+        let mut bar = Bar::new();
+        bar.register(left, "aaaaaa");
+        bar.register(right, "bbbbbb");
+
+        // Without a layout nothing is touched.
+        assert!(bar.fit().is_empty());
+
+        // Within a priority, elements are shortened right to left.
+        bar.set_layout(Some(layout(100)));
+        assert_eq!(fitted(&bar, left), None);
+        assert_eq!(fitted(&bar, right), Some(String::from("bbb\u{2026}")));
+
+        // Lower priorities go first.
+        bar.set_priority(right, 1);
+        assert_eq!(fitted(&bar, left), Some(String::from("aaa\u{2026}")));
+        assert_eq!(fitted(&bar, right), None);
+
+        // An element with no room left for `MIN_COLUMNS` is hidden.
+        bar.set_layout(Some(layout(70)));
+        assert_eq!(fitted(&bar, left), Some(String::new()));
+        assert_eq!(fitted(&bar, right), None);
+        assert_eq!(bar.frame(), b"%{l}%{c}%{r}bbbbbb\n".to_vec());
+
+        // The center section needs room on both sides.
+        let center = Position::new(Align::Center, 0);
+        bar.clear_priorities();
+        bar.set_layout(Some(layout(120)));
+        bar.register(left, "aa");
+        bar.register(center, "cc");
+        assert_eq!(fitted(&bar, right), Some(String::from("bbbb\u{2026}")));
+        assert_eq!(fitted(&bar, left), None);
+        assert_eq!(fitted(&bar, center), None);
+    }
+}
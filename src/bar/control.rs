@@ -0,0 +1,348 @@
+use std::os::unix::net::{
+    UnixListener,
+    UnixStream,
+};
+use std::os::unix::io::AsRawFd;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::io::prelude::*;
+use std::io::ErrorKind;
+use std::time::Duration;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::result;
+use std::str::FromStr;
+use libc;
+
+use event::{
+    Event,
+    EventLoop,
+    Token,
+};
+use util::{
+    Result,
+    Error,
+    ParseError,
+    ParseErrorKind,
+};
+
+/// How long the server waits for a client to send its request, and for it to take the
+/// response, in seconds.
+const REQUEST_TIMEOUT: u64 = 1;
+
+/// Longest request the server reads, in bytes.
+const MAX_REQUEST: usize = 64 * 1024;
+
+/// Returns the path of the control socket.
+///
+/// This is `$XDG_RUNTIME_DIR/bar.sock`, or a per-user socket in `/tmp` if that isn't set.
+pub fn socket_path() -> PathBuf {
+    match env::var("XDG_RUNTIME_DIR") {
+        Ok(dir) => {
+            let mut path = PathBuf::from(dir);
+            path.push("bar.sock");
+            path
+        },
+        Err(..) => {
+            let uid = unsafe { libc::getuid() };
+            PathBuf::from(format!("/tmp/bar-{}.sock", uid))
+        },
+    }
+}
+
+/// A request sent over the control socket.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Sets the text of a named slot.
+    Set(String, String),
+    /// Hides a module.
+    Hide(String),
+    /// Shows a hidden module.
+    Show(String),
+    /// Redraws the bar.
+    Refresh,
+    /// Hides or shows the whole bar.
+    Toggle,
+    /// Describes every module, as JSON if set.
+    State(bool),
+}
+
+impl FromStr for Command {
+    type Err = ParseError;
+
+    fn from_str(line: &str) -> result::Result<Command, ParseError> {
+        let line = line.trim();
+        let (cmd, rest) = match line.find(' ') {
+            Some(mid) => (&line[..mid], line[mid+1..].trim()),
+            None => (line, ""),
+        };
+        let missing = |field: &str| {
+            ParseError::with_field(line, field, ParseErrorKind::MissingValue)
+        };
+
+        match cmd {
+            "set" => {
+                let (name, text) = match rest.find(' ') {
+                    Some(mid) => (&rest[..mid], &rest[mid+1..]),
+                    None => (rest, ""),
+                };
+                if name.is_empty() {
+                    return Err(missing("slot"));
+                }
+                Ok(Command::Set(String::from(name), String::from(text)))
+            },
+            "hide" | "show" => {
+                if rest.is_empty() {
+                    return Err(missing("module"));
+                }
+                if cmd == "hide" {
+                    Ok(Command::Hide(String::from(rest)))
+                } else {
+                    Ok(Command::Show(String::from(rest)))
+                }
+            },
+            "refresh" => Ok(Command::Refresh),
+            "toggle" => Ok(Command::Toggle),
+            "state" => {
+                match rest {
+                    "" => Ok(Command::State(false)),
+                    "--json" => Ok(Command::State(true)),
+                    _ => Err(ParseError::with_field(line, "state", ParseErrorKind::UnknownValue)),
+                }
+            },
+            "" => Err(missing("command")),
+            _ => Err(ParseError::with_field(line, "command", ParseErrorKind::UnknownValue)),
+        }
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Command::Set(ref name, ref text) => write!(fmt, "set {} {}", name, text),
+            Command::Hide(ref name) => write!(fmt, "hide {}", name),
+            Command::Show(ref name) => write!(fmt, "show {}", name),
+            Command::Refresh => fmt.write_str("refresh"),
+            Command::Toggle => fmt.write_str("toggle"),
+            Command::State(false) => fmt.write_str("state"),
+            Command::State(true) => fmt.write_str("state --json"),
+        }
+    }
+}
+
+/// Listens on the control socket.
+///
+/// The protocol is a single request line from the client, answered by the server with any
+/// number of lines before it closes the connection. Failed requests are answered with a single
+/// line starting with `error: `.
+///
+/// Requests are read as they arrive rather than waited for, so a slow client can't hold up the
+/// rest of the bar.
+#[derive(Debug)]
+pub struct Server {
+    listener: UnixListener,
+    path: PathBuf,
+    src: Token,
+    /// Clients whose request hasn't arrived in full yet.
+    pending: Vec<Pending>,
+}
+
+/// A client waiting to be read from.
+#[derive(Debug)]
+struct Pending {
+    client: Client,
+    src: Token,
+    /// Gives up on the client once its time to send the request is up.
+    deadline: Token,
+}
+
+impl Server {
+    pub fn bind(path: &Path, ev: &mut EventLoop) -> Result<Server> {
+        // A socket left behind by a previous instance is only in the way if nothing answers it.
+        if path.exists() {
+            if UnixStream::connect(path).is_ok() {
+                return Err(Error::new(format!("`{}` is in use by another instance",
+                                              path.display())));
+            }
+            try!(fs::remove_file(path));
+        }
+
+        let listener = try!(UnixListener::bind(path));
+        try!(listener.set_nonblocking(true));
+        let src = try!(ev.add_fd(listener.as_raw_fd()));
+        Ok(Server {
+            listener: listener,
+            path: path.to_path_buf(),
+            src: src,
+            pending: Vec::new(),
+        })
+    }
+
+    /// Returns `true` if `event` came from the socket or one of its clients.
+    pub fn owns(&self, event: &Event) -> bool {
+        let token = event.token();
+        token == self.src || self.pending.iter().any(|p| p.src == token || p.deadline == token)
+    }
+
+    /// Handles an event from the socket or one of its clients, returning a request once one has
+    /// arrived in full.
+    ///
+    /// Malformed requests are answered and dropped here.
+    pub fn handle(&mut self, ev: &mut EventLoop, event: Event)
+        -> Result<Option<(Command, Client)>>
+    {
+        let token = event.token();
+        if token == self.src {
+            try!(self.accept(ev));
+            return Ok(None);
+        }
+        let idx = match self.pending.iter().position(|p| p.src == token || p.deadline == token) {
+            Some(idx) => idx,
+            None => return Ok(None),
+        };
+
+        let res = match event {
+            Event::Ready(..) => {
+                match self.pending[idx].client.read_request() {
+                    Ok(Some(cmd)) => Ok(cmd),
+                    Ok(None) => return Ok(None),
+                    Err(err) => Err(err),
+                }
+            },
+            _ => Err(Error::new("timed out waiting for the request")),
+        };
+
+        let pending = self.pending.remove(idx);
+        try!(ev.remove(pending.src));
+        try!(ev.remove(pending.deadline));
+        let mut client = pending.client;
+        match res {
+            Ok(cmd) => Ok(Some((cmd, client))),
+            Err(err) => {
+                client.error(&err);
+                Ok(None)
+            },
+        }
+    }
+
+    /// Drops every client and removes the server's sources from `ev`.
+    pub fn stop(&mut self, ev: &mut EventLoop) -> Result<()> {
+        for pending in self.pending.drain(..) {
+            try!(ev.remove(pending.src));
+            try!(ev.remove(pending.deadline));
+        }
+        ev.remove(self.src)
+    }
+
+    /// Accepts every pending connection, to be read from once it is readable.
+    fn accept(&mut self, ev: &mut EventLoop) -> Result<()> {
+        loop {
+            let stream = match self.listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                // The client gave up before it was accepted.
+                Err(ref e) if e.kind() == ErrorKind::ConnectionAborted => continue,
+                Err(e) => return Err(e.into()),
+            };
+            try!(stream.set_nonblocking(true));
+
+            let src = try!(ev.add_fd(stream.as_raw_fd()));
+            let deadline = match ev.add_oneshot(Duration::from_secs(REQUEST_TIMEOUT)) {
+                Ok(deadline) => deadline,
+                Err(err) => {
+                    try!(ev.remove(src));
+                    return Err(err);
+                },
+            };
+            self.pending.push(Pending {
+                client: Client {
+                    stream: stream,
+                    buf: Vec::new(),
+                },
+                src: src,
+                deadline: deadline,
+            });
+        }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// A connected client.
+#[derive(Debug)]
+pub struct Client {
+    stream: UnixStream,
+    /// What has been read of the request so far.
+    buf: Vec<u8>,
+}
+
+impl Client {
+    /// Sends a line of the response.
+    pub fn reply(&mut self, line: &str) {
+        // The client going away early is its own problem.
+        let _ = writeln!(self.stream, "{}", line);
+    }
+
+    /// Reports a failed request.
+    pub fn error<E>(&mut self, err: &E)
+        where E: fmt::Display
+    {
+        let _ = writeln!(self.stream, "error: {}", err);
+    }
+
+    /// Reads whatever the client sent, returning its request once a whole line has arrived.
+    fn read_request(&mut self) -> Result<Option<Command>> {
+        let mut chunk = [0u8; 1024];
+        let mut eof = false;
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => {
+                    eof = true;
+                    break;
+                },
+                Ok(n) => self.buf.extend_from_slice(&chunk[..n]),
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => {},
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        // A client may hang up right after its request instead of ending it with a newline.
+        let end = match self.buf.iter().position(|&b| b == b'\n') {
+            Some(end) => end,
+            None if eof && !self.buf.is_empty() => self.buf.len(),
+            None if eof => return Err(Error::new("connection closed without a request")),
+            None if self.buf.len() > MAX_REQUEST => return Err(Error::new("request too long")),
+            None => return Ok(None),
+        };
+        let cmd = try!(String::from_utf8_lossy(&self.buf[..end]).parse());
+
+        // The response is written in one go, and is only ever a few lines.
+        try!(self.stream.set_nonblocking(false));
+        try!(self.stream.set_write_timeout(Some(Duration::from_secs(REQUEST_TIMEOUT))));
+        Ok(Some(cmd))
+    }
+}
+
+/// Sends `cmd` to the server listening on `path`, returning its response.
+pub fn send(path: &Path, cmd: &Command) -> Result<String> {
+    let mut stream = try!(UnixStream::connect(path).map_err(|err| {
+        Error::new(format!("failed to connect to `{}`: {}", path.display(), err))
+    }));
+    try!(writeln!(stream, "{}", cmd));
+
+    let mut resp = String::new();
+    try!(stream.read_to_string(&mut resp));
+    if resp.starts_with("error: ") {
+        Err(Error::new(String::from(resp["error: ".len()..].trim_right())))
+    } else {
+        Ok(resp)
+    }
+}
@@ -2,6 +2,27 @@ use std::process::{
     Command,
     Stdio,
 };
+use std::result;
+use std::str::FromStr;
+use std::collections::{
+    BTreeMap,
+    VecDeque,
+};
+use std::ffi::CString;
+use std::mem;
+use std::cmp;
+use std::char;
+use std::fs::{
+    self,
+    File,
+};
+use std::io::prelude::*;
+use std::path::Path;
+use libc;
+use rustc_serialize::json::{
+    Json,
+    ToJson,
+};
 
 use bar::{
     Color,
@@ -9,15 +30,16 @@ use bar::{
     Formatter,
     Icon,
 };
-use pipe::PipeWriter;
+use sys;
 use util::{
     Result,
-    Error,
+    ParseError,
+    ParseErrorKind,
 };
 
+/// A source of data, fed one line of its output at a time.
 pub trait Provider {
-    fn is_data(&self, data: &str) -> bool;
-    fn consume(&mut self, data: &str);
+    fn consume(&mut self, data: &str) -> result::Result<(), ParseError>;
 }
 
 #[derive(Debug)]
@@ -26,20 +48,20 @@ pub struct WindowManager {
 }
 
 impl WindowManager {
-    pub fn new(output: &PipeWriter) -> Result<WindowManager> {
-        let outpipe = try!(output.stdio());
-        try!(Command::new("bspc")
-             .arg("control")
-             .arg("--subscribe")
-             .stdin(Stdio::null())
-             .stdout(outpipe)
-             .stderr(Stdio::inherit())
-             .spawn()
-             .map_err(|err| Error::from(err)));
-
-        Ok(WindowManager {
+    pub fn new() -> WindowManager {
+        WindowManager {
             dtops: Vec::new(),
-        })
+        }
+    }
+
+    /// Returns the command whose output this provider consumes.
+    pub fn command() -> Command {
+        let mut cmd = Command::new("bspc");
+        cmd.arg("control")
+           .arg("--subscribe")
+           .stdin(Stdio::null())
+           .stderr(Stdio::inherit());
+        cmd
     }
 }
 
@@ -53,20 +75,21 @@ impl Format for WindowManager {
 }
 
 impl Provider for WindowManager {
-    fn is_data(&self, data: &str) -> bool {
-        data.starts_with("WM")
-    }
-
-    fn consume(&mut self, data: &str) {
-        // If it is valid data, these cannot fail.
-        let start = data.find(':').unwrap() + 1;
-        let end = data.rfind(':').unwrap();
+    fn consume(&mut self, line: &str) -> result::Result<(), ParseError> {
+        let start = try!(line.find(':')
+                         .ok_or(ParseError::new(line, ParseErrorKind::MissingDelimiter(':')))) + 1;
+        let end = try!(line.rfind(':')
+                       .ok_or(ParseError::new(line, ParseErrorKind::MissingDelimiter(':'))));
+        if end < start {
+            return Err(ParseError::new(line, ParseErrorKind::MissingValue));
+        }
 
-        let data = &data[start..end];
+        let data = &line[start..end];
 
         for (i, dtop) in data.split(':').enumerate() {
             let mut chars = dtop.chars();
-            let status = chars.next().unwrap();
+            let status = try!(chars.next()
+                              .ok_or(ParseError::new(line, ParseErrorKind::MissingValue)));
             let (occupied, focused) = parse_status(status);
 
             if self.dtops.len() > i {
@@ -75,7 +98,13 @@ impl Provider for WindowManager {
                 self.dtops.push(Desktop::new(occupied, focused));
             }
         }
+        Ok(())
+    }
+}
 
+impl ToJson for WindowManager {
+    fn to_json(&self) -> Json {
+        self.dtops.to_json()
     }
 }
 
@@ -126,11 +155,124 @@ impl Format for Desktop {
     }
 }
 
+impl ToJson for Desktop {
+    fn to_json(&self) -> Json {
+        let mut obj = BTreeMap::new();
+        obj.insert(String::from("occupied"), self.occupied.to_json());
+        obj.insert(String::from("focused"), self.focused.to_json());
+        Json::Object(obj)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct System {
     pub bat: Battery,
     pub datetime: DateTime,
     pub cpu: Cpu,
+    history: BTreeMap<&'static str, History>,
+}
+
+/// Number of samples kept of each series.
+pub const HISTORY_LEN: usize = 64;
+
+/// Series `System` keeps a history of: the average and per-core CPU usage in percent, memory
+/// usage in percent, and network rates.
+pub const SERIES: &'static [&'static str] = &[
+    "cpu", "cpu0", "cpu1", "cpu2", "cpu3", "mem", "net_down", "net_up",
+];
+
+/// The last samples of a series, oldest first.
+#[derive(Debug, Clone)]
+pub struct History {
+    samples: VecDeque<f64>,
+    capacity: usize,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> History {
+        History {
+            samples: VecDeque::with_capacity(capacity),
+            capacity: capacity,
+        }
+    }
+
+    /// Adds a sample, dropping the oldest one if the history is full.
+    pub fn push(&mut self, sample: f64) {
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    pub fn last(&self) -> Option<f64> {
+        self.samples.back().cloned()
+    }
+
+    /// The largest of the last `n` samples.
+    pub fn max(&self, n: usize) -> Option<f64> {
+        self.recent(n).fold(None, |max, s| Some(max.map_or(s, |m: f64| m.max(s))))
+    }
+
+    /// Iterates over the last `n` samples, oldest first.
+    pub fn recent<'a>(&'a self, n: usize) -> Box<Iterator<Item=f64> + 'a> {
+        let skip = self.samples.len() - cmp::min(n, self.samples.len());
+        Box::new(self.samples.iter().skip(skip).cloned())
+    }
+}
+
+impl ToJson for History {
+    fn to_json(&self) -> Json {
+        self.samples.iter().cloned().collect::<Vec<f64>>().to_json()
+    }
+}
+
+const BLOCKS: [char; 8] = [
+    '\u{2581}', '\u{2582}', '\u{2583}', '\u{2584}',
+    '\u{2585}', '\u{2586}', '\u{2587}', '\u{2588}',
+];
+
+/// Draws the last samples of a history as a graph of block characters, one per sample.
+pub struct Sparkline<'a> {
+    history: &'a History,
+    width: usize,
+    max: Option<f64>,
+}
+
+impl<'a> Sparkline<'a> {
+    /// Graphs the last `width` samples of `history`. Samples are scaled to `max`, or to the
+    /// largest sample shown if there is none.
+    pub fn new(history: &'a History, width: usize, max: Option<f64>) -> Sparkline<'a> {
+        Sparkline {
+            history: history,
+            width: width,
+            max: max,
+        }
+    }
+}
+
+impl<'a> Format for Sparkline<'a> {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<()> {
+        let max = self.max.or_else(|| self.history.max(self.width)).unwrap_or(0.0);
+        let mut graph = String::new();
+        // Keep the graph the same width while the history fills up.
+        for _ in self.history.len()..self.width {
+            graph.push(' ');
+        }
+        for sample in self.history.recent(self.width) {
+            let level = if max > 0.0 { sample / max } else { 0.0 };
+            let i = (level * (BLOCKS.len() - 1) as f64).round();
+            graph.push(BLOCKS[i.max(0.0).min((BLOCKS.len() - 1) as f64) as usize]);
+        }
+        fmt.write(&*graph)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -145,6 +287,56 @@ impl Format for DateTime {
     }
 }
 
+impl ToJson for DateTime {
+    fn to_json(&self) -> Json {
+        let mut obj = BTreeMap::new();
+        obj.insert(String::from("date"), self.date.to_json());
+        obj.insert(String::from("time"), self.time.to_json());
+        Json::Object(obj)
+    }
+}
+
+impl DateTime {
+    /// Updates the date and time from the system clock, returning `true` if either changed.
+    pub fn update(&mut self) -> bool {
+        let (date, time) = match (strftime_now("%a %b %-d"), strftime_now("%H:%M")) {
+            (Some(date), Some(time)) => (date, time),
+            _ => return false,
+        };
+        let changed = date != self.date || time != self.time;
+        self.date = date;
+        self.time = time;
+        changed
+    }
+}
+
+/// Formats the current local time according to `format`.
+fn strftime_now(format: &str) -> Option<String> {
+    let format = match CString::new(format) {
+        Ok(format) => format,
+        Err(..) => return None,
+    };
+    unsafe {
+        let mut now: libc::timespec = mem::zeroed();
+        let mut tm: libc::tm = mem::zeroed();
+        if libc::clock_gettime(libc::CLOCK_REALTIME, &mut now) != 0 ||
+           libc::localtime_r(&now.tv_sec, &mut tm).is_null() {
+            return None;
+        }
+
+        let mut buf = [0u8; 64];
+        let n = sys::strftime(buf.as_mut_ptr() as *mut libc::c_char,
+                              buf.len() as libc::size_t,
+                              format.as_ptr(),
+                              &tm);
+        if n == 0 {
+            None
+        } else {
+            Some(String::from_utf8_lossy(&buf[..n as usize]).into_owned())
+        }
+    }
+}
+
 impl Default for DateTime {
     fn default() -> DateTime {
         DateTime {
@@ -159,11 +351,215 @@ pub struct Battery {
     pct: usize,
     pub time: String,
     pub status: BatStatus,
+    style: BatteryStyle,
+    /// Frame of the charging animation.
+    frame: usize,
+    /// Every battery in the system, if the kernel lists any.
+    cells: Vec<Cell>,
+    /// Whether an AC adapter is plugged in, if there is one.
+    ac: Option<bool>,
+}
+
+/// One of several batteries.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cell {
+    name: String,
+    pct: usize,
+    status: BatStatus,
+    /// Capacity when full, in µWh or µAh, as the kernel reports it.
+    full: u64,
+}
+
+impl ToJson for Cell {
+    fn to_json(&self) -> Json {
+        let mut obj = BTreeMap::new();
+        obj.insert(String::from("name"), self.name.to_json());
+        obj.insert(String::from("pct"), self.pct.to_json());
+        obj.insert(String::from("status"), self.status.to_json());
+        obj.insert(String::from("full"), self.full.to_json());
+        Json::Object(obj)
+    }
+}
+
+/// Where the kernel lists batteries and AC adapters.
+const POWER_SUPPLY_DIR: &'static str = "/sys/class/power_supply";
+
+impl Battery {
+    /// Charge in percent.
+    pub fn pct(&self) -> usize {
+        self.pct
+    }
+
+    /// Reads every battery and AC adapter the kernel lists, returning `true` if anything
+    /// changed. The charge of several batteries is weighted by their capacity.
+    ///
+    /// Without any batteries listed, the values conky reports are kept.
+    pub fn refresh(&mut self) -> bool {
+        let (cells, ac) = read_power_supplies(Path::new(POWER_SUPPLY_DIR));
+        if cells == self.cells && ac == self.ac {
+            return false;
+        }
+
+        if !cells.is_empty() {
+            let total = cells.iter().fold(0, |sum, c| sum + c.full);
+            self.pct = if total > 0 {
+                (cells.iter().fold(0, |sum, c| sum + c.pct as u64 * c.full) / total) as usize
+            } else {
+                cells.iter().fold(0, |sum, c| sum + c.pct) / cells.len()
+            };
+            self.status = if cells.iter().any(|c| c.status == BatStatus::Charging) {
+                BatStatus::Charging
+            } else if cells.iter().any(|c| c.status == BatStatus::Discharging) {
+                BatStatus::Discharging
+            } else if cells.iter().all(|c| c.status == BatStatus::Full) {
+                BatStatus::Full
+            } else if cells.iter().all(|c| c.status == BatStatus::Empty) {
+                BatStatus::Empty
+            } else {
+                BatStatus::Unknown
+            };
+        }
+        self.cells = cells;
+        self.ac = ac;
+        true
+    }
+
+    pub fn set_style(&mut self, style: BatteryStyle) {
+        self.style = style;
+        self.frame = 0;
+    }
+
+    /// Advances the charging animation, returning `true` if the icon changed.
+    pub fn tick(&mut self) -> bool {
+        if !self.style.animate || self.status != BatStatus::Charging {
+            return false;
+        }
+        self.frame = self.frame.wrapping_add(1);
+        true
+    }
+
+    /// Level of charge, from 0 for empty to 3 for full.
+    fn level(&self) -> usize {
+        self.style.breakpoints.iter().take_while(|&&b| self.pct >= b).count()
+    }
+
+    fn icon(&self) -> char {
+        let levels = [self.style.empty, self.style.low, self.style.half, self.style.full];
+        match self.status {
+            BatStatus::Full => self.style.full,
+            BatStatus::Empty => self.style.empty,
+            // Fill up from the current level to full, over and over.
+            BatStatus::Charging if self.style.animate => {
+                let level = self.level();
+                levels[level + self.frame % (levels.len() - level)]
+            },
+            BatStatus::Charging => self.style.charging,
+            BatStatus::Discharging | BatStatus::Unknown => levels[self.level()],
+        }
+    }
 }
 
 impl Format for Battery {
     fn fmt(&self, fmt: &mut Formatter) -> Result<()> {
-        fmt.write(&*format!(" bat: {:03}", self.pct))
+        // A trailing `+` means the AC adapter is plugged in.
+        let ac = if self.ac == Some(true) { "+" } else { "" };
+        fmt.write(&*format!(" {} {:03}{}", self.icon(), self.pct, ac))
+    }
+}
+
+/// Reads the batteries in `dir`, sorted by name, and whether any AC adapter in it is online.
+fn read_power_supplies(dir: &Path) -> (Vec<Cell>, Option<bool>) {
+    let mut cells = Vec::new();
+    let mut ac = None;
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(..) => return (cells, ac),
+    };
+
+    for entry in entries.filter_map(|e| e.ok()) {
+        let path = entry.path();
+        match read_attr(&path, "type").as_ref().map(|t| &**t) {
+            Some("Mains") => {
+                let online = read_attr(&path, "online").map_or(false, |o| o == "1");
+                ac = Some(ac.unwrap_or(false) || online);
+            },
+            Some("Battery") => {
+                let pct = match read_attr(&path, "capacity").and_then(|c| c.parse().ok()) {
+                    Some(pct) => pct,
+                    None => continue,
+                };
+                let full = read_attr(&path, "energy_full")
+                    .or_else(|| read_attr(&path, "charge_full"))
+                    .and_then(|f| f.parse().ok())
+                    .unwrap_or(0);
+                // `Not charging` ends up as unknown.
+                let status = read_attr(&path, "status")
+                    .and_then(|s| s.chars().next())
+                    .map_or(BatStatus::Unknown, BatStatus::from);
+                cells.push(Cell {
+                    name: entry.file_name().to_string_lossy().into_owned(),
+                    pct: pct,
+                    status: status,
+                    full: full,
+                });
+            },
+            _ => {},
+        }
+    }
+    cells.sort_by(|a, b| a.name.cmp(&b.name));
+    (cells, ac)
+}
+
+/// Reads an attribute of a device in sysfs.
+fn read_attr(dir: &Path, attr: &str) -> Option<String> {
+    let mut buf = String::new();
+    match File::open(dir.join(attr)).and_then(|mut f| f.read_to_string(&mut buf)) {
+        Ok(..) => Some(String::from(buf.trim())),
+        Err(..) => None,
+    }
+}
+
+/// How the battery is drawn.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatteryStyle {
+    /// Percentages from which the low, half and full icons are shown, in ascending order.
+    pub breakpoints: [usize; 3],
+    /// Fill up the icon while charging, rather than showing the charging icon.
+    pub animate: bool,
+    pub empty: char,
+    pub low: char,
+    pub half: char,
+    pub full: char,
+    pub charging: char,
+}
+
+impl Default for BatteryStyle {
+    fn default() -> BatteryStyle {
+        BatteryStyle {
+            breakpoints: [10, 40, 80],
+            animate: false,
+            empty: icon_char(Icon::BatEmpty),
+            low: icon_char(Icon::BatLow),
+            half: icon_char(Icon::BatHalf),
+            full: icon_char(Icon::BatFull),
+            charging: icon_char(Icon::BatCharging),
+        }
+    }
+}
+
+fn icon_char(icon: Icon) -> char {
+    char::from_u32(icon as u32).unwrap_or('?')
+}
+
+impl ToJson for Battery {
+    fn to_json(&self) -> Json {
+        let mut obj = BTreeMap::new();
+        obj.insert(String::from("pct"), self.pct.to_json());
+        obj.insert(String::from("time"), self.time.to_json());
+        obj.insert(String::from("status"), self.status.to_json());
+        obj.insert(String::from("ac"), self.ac.to_json());
+        obj.insert(String::from("batteries"), self.cells.to_json());
+        Json::Object(obj)
     }
 }
 
@@ -173,6 +569,10 @@ impl Default for Battery {
             pct: 0,
             time: String::from("0:00"),
             status: BatStatus::Unknown,
+            style: BatteryStyle::default(),
+            frame: 0,
+            cells: Vec::new(),
+            ac: None,
         }
     }
 }
@@ -198,6 +598,19 @@ impl From<char> for BatStatus {
     }
 }
 
+impl ToJson for BatStatus {
+    fn to_json(&self) -> Json {
+        let status = match *self {
+            BatStatus::Charging => "charging",
+            BatStatus::Discharging => "discharging",
+            BatStatus::Full => "full",
+            BatStatus::Empty => "empty",
+            BatStatus::Unknown => "unknown",
+        };
+        Json::String(String::from(status))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Cpu {
     temp: usize,
@@ -205,6 +618,13 @@ pub struct Cpu {
     usage: [usize; 4],
 }
 
+impl Cpu {
+    /// Temperature in degrees Celsius.
+    pub fn temp(&self) -> usize {
+        self.temp
+    }
+}
+
 impl Format for Cpu {
     fn fmt(&self, fmt: &mut Formatter) -> Result<()> {
         fmt.write(&*format!(" temp: {:03} freq: {:.2}/{:.2}/{:.2}/{:.2} use: {:03}/{:03}/{:03}/{:03}",
@@ -215,6 +635,16 @@ impl Format for Cpu {
     }
 }
 
+impl ToJson for Cpu {
+    fn to_json(&self) -> Json {
+        let mut obj = BTreeMap::new();
+        obj.insert(String::from("temp"), self.temp.to_json());
+        obj.insert(String::from("freq"), self.freq.to_vec().to_json());
+        obj.insert(String::from("usage"), self.usage.to_vec().to_json());
+        Json::Object(obj)
+    }
+}
+
 impl Default for Cpu {
     fn default() -> Cpu {
         Cpu {
@@ -226,35 +656,41 @@ impl Default for Cpu {
 }
 
 impl System {
-    pub fn new(output: &PipeWriter) -> Result<System> {
-        let outpipe = try!(output.stdio());
-
-        try!(Command::new("conky")
-            .stdin(Stdio::null())
-            .stdout(outpipe)
-            .stderr(Stdio::inherit())
-            .spawn()
-            .map_err(|err| Error::from(err)));
-
-        Ok(System {
+    pub fn new() -> System {
+        System {
             bat: Battery::default(),
             datetime: DateTime::default(),
             cpu: Cpu::default(),
-        })
+            history: SERIES.iter().map(|&s| (s, History::new(HISTORY_LEN))).collect(),
+        }
     }
-}
 
-impl Provider for System {
-    fn is_data(&self, data: &str) -> bool {
-        // Any data that doesn't belong to the window manager.
-        !data.starts_with("WM")
+    /// The history of one of `SERIES`.
+    pub fn history(&self, series: &str) -> Option<&History> {
+        self.history.get(series)
     }
 
-    fn consume(&mut self, data: &str) {
-        let mid = data.find('=').unwrap();
-        let key = data[..mid].trim();
-        let val = data[mid+1..].trim();
+    fn record(&mut self, series: &str, sample: f64) {
+        if let Some(history) = self.history.get_mut(series) {
+            history.push(sample);
+        }
+    }
 
+    /// Returns the command whose output this provider consumes.
+    pub fn command() -> Command {
+        let mut cmd = Command::new("conky");
+        cmd.stdin(Stdio::null())
+           .stderr(Stdio::inherit());
+        cmd
+    }
+}
+
+impl Provider for System {
+    fn consume(&mut self, line: &str) -> result::Result<(), ParseError> {
+        let mid = try!(line.find('=')
+                       .ok_or(ParseError::new(line, ParseErrorKind::MissingDelimiter('='))));
+        let key = line[..mid].trim();
+        let val = line[mid+1..].trim();
 
         match key {
             "BAT_TIME" => {
@@ -262,12 +698,26 @@ impl Provider for System {
                 self.bat.time = time;
             },
             "BAT_STATUS" => {
-                let stat_char = val.chars().next().unwrap();
-                self.bat.status = BatStatus::from(stat_char);
+                let stat_char = try!(val.chars().next()
+                                     .ok_or(ParseError::with_field(line, key,
+                                                                   ParseErrorKind::MissingValue)));
+
+                let start = try!(val.find(char::is_numeric)
+                                 .ok_or(ParseError::with_field(line, key,
+                                                               ParseErrorKind::MissingValue)));
+                let missing = ParseErrorKind::MissingDelimiter('%');
+                let end = try!(val.rfind('%')
+                               .ok_or(ParseError::with_field(line, key, missing)));
+                if end < start {
+                    return Err(ParseError::with_field(line, key, ParseErrorKind::MissingValue));
+                }
+                let pct = try!(parse_num(line, key, val[start..end].trim()));
 
-                let start = val.find(char::is_numeric).unwrap();
-                let end = val.rfind('%').unwrap();
-                self.bat.pct = String::from(val[start..end].trim()).parse().unwrap();
+                // The kernel's view of every battery beats conky's view of the first.
+                if self.bat.cells.is_empty() {
+                    self.bat.status = BatStatus::from(stat_char);
+                    self.bat.pct = pct;
+                }
             },
             "TIME" => {
                 self.datetime.time = String::from(val);
@@ -276,23 +726,55 @@ impl Provider for System {
                 self.datetime.date = String::from(val);
             },
             "TEMP" => {
-                self.cpu.temp = val.parse().unwrap();
+                self.cpu.temp = try!(parse_num(line, key, val));
             },
             "CPU" => {
+                let mut usage = self.cpu.usage;
                 for (i, core) in val.split_whitespace().enumerate().take(4) {
-                    self.cpu.usage[i] = core.parse().unwrap();
+                    usage[i] = try!(parse_num(line, key, core));
+                }
+                self.cpu.usage = usage;
+
+                let avg = usage.iter().fold(0, |sum, &u| sum + u) as f64 / usage.len() as f64;
+                self.record("cpu", avg);
+                for (i, &core) in usage.iter().enumerate() {
+                    self.record(&format!("cpu{}", i), core as f64);
+                }
+            },
+            "MEM" => {
+                let mem = try!(parse_num(line, key, val.trim_right_matches('%')));
+                self.record("mem", mem);
+            },
+            "NET" => {
+                // `<down> <up>`, in whatever unit conky is told to use.
+                let mut rates = val.split_whitespace();
+                for series in &["net_down", "net_up"] {
+                    let rate = try!(rates.next()
+                                    .ok_or(ParseError::with_field(line, key,
+                                                                  ParseErrorKind::MissingValue)));
+                    let rate = try!(parse_num(line, key, rate));
+                    self.record(series, rate);
                 }
             },
             "CPU_FREQ" => {
+                let mut freq = self.cpu.freq;
                 for (i, core) in val.split_whitespace().enumerate().take(4) {
-                    self.cpu.freq[i] = core.parse().unwrap();
+                    freq[i] = try!(parse_num(line, key, core));
                 }
+                self.cpu.freq = freq;
             },
             _ => {},
         }
+        Ok(())
     }
 }
 
+fn parse_num<T>(line: &str, field: &str, val: &str) -> result::Result<T, ParseError>
+    where T: FromStr
+{
+    val.parse().map_err(|_| ParseError::with_field(line, field, ParseErrorKind::InvalidNumber))
+}
+
 fn parse_bat_time(data: &str) -> String {
     let mut time = String::new();
 
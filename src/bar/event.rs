@@ -0,0 +1,424 @@
+use std::collections::{
+    BTreeMap,
+    VecDeque,
+};
+use std::os::unix::io::{
+    RawFd,
+    AsRawFd,
+    FromRawFd,
+};
+use std::io::{
+    self,
+    ErrorKind,
+};
+use std::time::Duration;
+use std::path::Path;
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::mem;
+use std::ptr;
+use std::slice;
+use libc;
+
+use pipe::{
+    FileDesc,
+    LineReader,
+    PipeReader,
+    Poller,
+};
+use sys;
+use util::{
+    Result,
+    Error,
+};
+
+/// Identifies a source registered with an `EventLoop`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Token(usize);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// A complete line was read from a pipe.
+    Line(Token, String),
+    /// A pipe was closed by all of its writers, and has been removed from the loop.
+    Closed(Token),
+    /// A timer expired.
+    Timer(Token),
+    /// A signal was delivered.
+    Signal(Token, libc::c_int),
+    /// A watched path changed. For directories, carries the name of the entry that changed.
+    Changed(Token, Option<String>),
+    /// A file descriptor added with `add_fd` is readable.
+    Ready(Token),
+}
+
+impl Event {
+    /// The source the event came from.
+    pub fn token(&self) -> Token {
+        match *self {
+            Event::Line(t, _) |
+            Event::Closed(t) |
+            Event::Timer(t) |
+            Event::Signal(t, _) |
+            Event::Changed(t, _) |
+            Event::Ready(t) => t,
+        }
+    }
+}
+
+/// What changes a watch reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Watch {
+    /// Every write to a file.
+    File,
+    /// Files in a directory being written or replaced.
+    Dir,
+    /// Files in a directory being created, as well as written or replaced.
+    Entries,
+}
+
+#[derive(Debug)]
+enum Source {
+    Pipe(LineReader),
+    Timer(Timer),
+    Signals(Signals),
+    Inotify(Inotify),
+    Fd(RawFd),
+}
+
+impl AsRawFd for Source {
+    fn as_raw_fd(&self) -> RawFd {
+        match *self {
+            Source::Pipe(ref reader) => reader.as_raw_fd(),
+            Source::Timer(ref timer) => timer.as_raw_fd(),
+            Source::Signals(ref signals) => signals.as_raw_fd(),
+            Source::Inotify(ref inotify) => inotify.as_raw_fd(),
+            Source::Fd(fd) => fd,
+        }
+    }
+}
+
+/// Waits on pipes, timers, signals and file changes at once, turning whatever is ready into
+/// `Event`s.
+#[derive(Debug)]
+pub struct EventLoop {
+    poller: Poller,
+    sources: BTreeMap<Token, Source>,
+    /// Tokens are never reused, so an event still queued for a removed source can't be
+    /// mistaken for one from a source added after it.
+    next_token: usize,
+    pending: VecDeque<Event>,
+}
+
+impl EventLoop {
+    pub fn new() -> Result<EventLoop> {
+        Ok(EventLoop {
+            poller: try!(Poller::new()),
+            sources: BTreeMap::new(),
+            next_token: 0,
+            pending: VecDeque::new(),
+        })
+    }
+
+    /// Adds a pipe, producing an `Event::Line` for each line written to it.
+    pub fn add_pipe(&mut self, reader: PipeReader) -> Result<Token> {
+        let reader = try!(LineReader::new(reader));
+        self.add(Source::Pipe(reader))
+    }
+
+    /// Adds a timer producing an `Event::Timer` every `interval`.
+    pub fn add_timer(&mut self, interval: Duration) -> Result<Token> {
+        let timer = try!(Timer::new());
+        try!(timer.set(interval, Some(interval)));
+        self.add(Source::Timer(timer))
+    }
+
+    /// Adds a timer producing a single `Event::Timer` after `delay`.
+    pub fn add_oneshot(&mut self, delay: Duration) -> Result<Token> {
+        let timer = try!(Timer::new());
+        try!(timer.set(delay, None));
+        self.add(Source::Timer(timer))
+    }
+
+    /// Blocks `signals`, producing an `Event::Signal` whenever one is delivered instead.
+    pub fn add_signals(&mut self, signals: &[libc::c_int]) -> Result<Token> {
+        let signals = try!(Signals::new(signals));
+        self.add(Source::Signals(signals))
+    }
+
+    /// Watches `path`, producing an `Event::Changed` whenever it changes.
+    pub fn add_watch(&mut self, path: &Path, watch: Watch) -> Result<Token> {
+        let inotify = try!(Inotify::new(path, watch));
+        self.add(Source::Inotify(inotify))
+    }
+
+    /// Polls `fd` for readability, producing an `Event::Ready` whenever it is.
+    ///
+    /// The descriptor remains owned by the caller, who is responsible for draining it and for
+    /// removing it before it is closed.
+    pub fn add_fd(&mut self, fd: RawFd) -> Result<Token> {
+        self.add(Source::Fd(fd))
+    }
+
+    /// Removes the source identified by `token`, discarding any of its events not yet returned.
+    pub fn remove(&mut self, token: Token) -> Result<()> {
+        if let Some(source) = self.sources.remove(&token) {
+            try!(self.poller.remove(source.as_raw_fd()));
+        }
+        self.pending.retain(|ev| ev.token() != token);
+        Ok(())
+    }
+
+    /// Returns `true` if `next` can return without blocking.
+    pub fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Blocks until the next event is available.
+    pub fn next(&mut self) -> Result<Event> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Ok(event);
+            }
+
+            for id in try!(self.poller.wait(-1)) {
+                try!(self.dispatch(Token(id as usize)));
+            }
+        }
+    }
+
+    fn add(&mut self, source: Source) -> Result<Token> {
+        let token = Token(self.next_token);
+        try!(self.poller.add(source.as_raw_fd(), token.0 as u64));
+        self.next_token += 1;
+        self.sources.insert(token, source);
+        Ok(token)
+    }
+
+    fn dispatch(&mut self, token: Token) -> Result<()> {
+        let closed = match self.sources.get_mut(&token) {
+            Some(&mut Source::Pipe(ref mut reader)) => {
+                let eof = try!(reader.fill());
+                while let Some(line) = reader.next_line() {
+                    self.pending.push_back(Event::Line(token, line));
+                }
+                if eof {
+                    if let Some(line) = reader.take_partial() {
+                        self.pending.push_back(Event::Line(token, line));
+                    }
+                }
+                eof
+            },
+            Some(&mut Source::Timer(ref timer)) => {
+                if try!(timer.read()) > 0 {
+                    self.pending.push_back(Event::Timer(token));
+                }
+                false
+            },
+            Some(&mut Source::Signals(ref signals)) => {
+                while let Some(sig) = try!(signals.read()) {
+                    self.pending.push_back(Event::Signal(token, sig));
+                }
+                false
+            },
+            Some(&mut Source::Inotify(ref inotify)) => {
+                for name in try!(inotify.read()) {
+                    self.pending.push_back(Event::Changed(token, name));
+                }
+                false
+            },
+            Some(&mut Source::Fd(..)) => {
+                self.pending.push_back(Event::Ready(token));
+                false
+            },
+            _ => false,
+        };
+
+        if closed {
+            if let Some(source) = self.sources.remove(&token) {
+                try!(self.poller.remove(source.as_raw_fd()));
+            }
+            self.pending.push_back(Event::Closed(token));
+        }
+        Ok(())
+    }
+}
+
+/// A `timerfd` on the monotonic clock.
+#[derive(Debug)]
+pub struct Timer(FileDesc);
+
+impl Timer {
+    pub fn new() -> Result<Timer> {
+        let fd = unsafe {
+            sys::timerfd_create(libc::CLOCK_MONOTONIC, sys::TFD_CLOEXEC | sys::TFD_NONBLOCK)
+        };
+        if fd == -1 {
+            Err(io::Error::last_os_error().into())
+        } else {
+            unsafe {
+                Ok(Timer(FileDesc::from_raw_fd(fd)))
+            }
+        }
+    }
+
+    /// Arms the timer to expire after `value`, and then every `interval` if one is given.
+    pub fn set(&self, value: Duration, interval: Option<Duration>) -> Result<()> {
+        // A zero value would disarm the timer instead.
+        let value = if value == Duration::new(0, 0) { Duration::new(0, 1) } else { value };
+        let spec = sys::itimerspec {
+            it_interval: timespec(interval.unwrap_or(Duration::new(0, 0))),
+            it_value: timespec(value),
+        };
+        let ret = unsafe {
+            sys::timerfd_settime(self.0.as_raw_fd(), 0, &spec, ptr::null_mut())
+        };
+        if ret == -1 {
+            Err(io::Error::last_os_error().into())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns the number of expirations since the last read.
+    pub fn read(&self) -> Result<u64> {
+        let mut buf = [0u8; 8];
+        match self.0.read(&mut buf) {
+            Ok(8) => Ok(unsafe { mem::transmute::<[u8; 8], u64>(buf) }),
+            Ok(..) => Ok(0),
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => Ok(0),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl AsRawFd for Timer {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+fn timespec(dur: Duration) -> libc::timespec {
+    libc::timespec {
+        tv_sec: dur.as_secs() as libc::time_t,
+        tv_nsec: dur.subsec_nanos() as libc::c_long,
+    }
+}
+
+/// A `signalfd` receiving a set of signals that have been blocked for normal delivery.
+#[derive(Debug)]
+pub struct Signals(FileDesc);
+
+impl Signals {
+    pub fn new(signals: &[libc::c_int]) -> Result<Signals> {
+        unsafe {
+            let mut set: libc::sigset_t = mem::zeroed();
+            libc::sigemptyset(&mut set);
+            for &sig in signals {
+                libc::sigaddset(&mut set, sig);
+            }
+
+            let ret = libc::pthread_sigmask(sys::SIG_BLOCK, &set, ptr::null_mut());
+            if ret != 0 {
+                return Err(io::Error::from_raw_os_error(ret).into());
+            }
+
+            let fd = sys::signalfd(-1, &set, sys::SFD_CLOEXEC | sys::SFD_NONBLOCK);
+            if fd == -1 {
+                Err(io::Error::last_os_error().into())
+            } else {
+                Ok(Signals(FileDesc::from_raw_fd(fd)))
+            }
+        }
+    }
+
+    /// Returns the next pending signal, if any.
+    pub fn read(&self) -> Result<Option<libc::c_int>> {
+        let mut info: sys::signalfd_siginfo = unsafe { mem::zeroed() };
+        let size = mem::size_of::<sys::signalfd_siginfo>();
+        let buf = unsafe {
+            slice::from_raw_parts_mut(&mut info as *mut _ as *mut u8, size)
+        };
+        match self.0.read(buf) {
+            Ok(n) if n == size => Ok(Some(info.ssi_signo as libc::c_int)),
+            Ok(..) => Ok(None),
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl AsRawFd for Signals {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+/// An `inotify` instance watching a single path.
+#[derive(Debug)]
+pub struct Inotify(FileDesc);
+
+impl Inotify {
+    pub fn new(path: &Path, watch: Watch) -> Result<Inotify> {
+        let mask = match watch {
+            Watch::File => sys::IN_MODIFY | sys::IN_CLOSE_WRITE,
+            Watch::Dir => sys::IN_CLOSE_WRITE | sys::IN_MOVED_TO,
+            Watch::Entries => sys::IN_CLOSE_WRITE | sys::IN_MOVED_TO | sys::IN_CREATE,
+        };
+        let cpath = try!(CString::new(path.as_os_str().as_bytes())
+                         .map_err(|_| Error::new("path contains a nul byte")));
+
+        unsafe {
+            let fd = sys::inotify_init1(sys::IN_CLOEXEC | sys::IN_NONBLOCK);
+            if fd == -1 {
+                return Err(io::Error::last_os_error().into());
+            }
+            let fd = FileDesc::from_raw_fd(fd);
+            if sys::inotify_add_watch(fd.as_raw_fd(), cpath.as_ptr(), mask) == -1 {
+                let err = io::Error::last_os_error();
+                return Err(Error::new(format!("failed to watch `{}`: {}", path.display(), err)));
+            }
+            Ok(Inotify(fd))
+        }
+    }
+
+    /// Returns the changes since the last read, as the names of the entries that changed.
+    pub fn read(&self) -> Result<Vec<Option<String>>> {
+        let hdr = mem::size_of::<sys::inotify_event>();
+        // Events are read into a `u32` buffer so their headers are suitably aligned.
+        let mut buf = [0u32; 1024];
+        let mut names = Vec::new();
+
+        loop {
+            let bytes = unsafe {
+                slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, buf.len() * 4)
+            };
+            let n = match self.0.read(bytes) {
+                Ok(n) => n,
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => return Ok(names),
+                Err(e) => return Err(e.into()),
+            };
+
+            let mut off = 0;
+            while off + hdr <= n {
+                let event = unsafe {
+                    ptr::read(bytes.as_ptr().offset(off as isize) as *const sys::inotify_event)
+                };
+                let name = &bytes[off + hdr..off + hdr + event.len as usize];
+                let name = &name[..name.iter().position(|&b| b == 0).unwrap_or(name.len())];
+                if name.is_empty() {
+                    names.push(None);
+                } else {
+                    names.push(Some(String::from_utf8_lossy(name).into_owned()));
+                }
+                off += hdr + event.len as usize;
+            }
+        }
+    }
+}
+
+impl AsRawFd for Inotify {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
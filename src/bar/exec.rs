@@ -0,0 +1,288 @@
+use std::process::{
+    Child,
+    Command,
+    Stdio,
+};
+use std::os::unix::process::CommandExt;
+use std::collections::BTreeMap;
+use std::time::Duration;
+use libc;
+
+use rustc_serialize::json::{
+    Json,
+    ToJson,
+};
+
+use bar::{
+    Align,
+    Format,
+    Formatter,
+    Position,
+    Style,
+    Styled,
+};
+use event::{
+    Event,
+    EventLoop,
+    Token,
+};
+use pipe;
+use util::{
+    self,
+    text,
+    Result,
+};
+
+/// A module showing the output of a command run on an interval.
+///
+/// Runs never overlap, and are killed if they take longer than the timeout. The output of the
+/// last successful run is kept until the next one succeeds.
+#[derive(Debug)]
+pub struct Exec {
+    name: String,
+    cmd: String,
+    pos: Position,
+    timeout: Duration,
+    show_errors: bool,
+    timer: Token,
+    run: Option<Run>,
+    output: Option<String>,
+    failure: Option<String>,
+}
+
+/// A run in progress.
+#[derive(Debug)]
+struct Run {
+    child: Child,
+    /// Exit status, once the child has been reaped.
+    status: Option<libc::c_int>,
+    stdout: Option<Token>,
+    stderr: Option<Token>,
+    deadline: Token,
+    out: Vec<String>,
+    err: Vec<String>,
+}
+
+impl Exec {
+    /// Creates the module and starts its first run.
+    pub fn new(conf: &util::Exec, ev: &mut EventLoop) -> Result<Exec> {
+        let align: Align = try!(conf.align.parse());
+        let interval = Duration::from_secs(conf.interval);
+        let timer = try!(ev.add_timer(interval));
+
+        let mut exec = Exec {
+            name: conf.name.clone(),
+            cmd: conf.command.clone(),
+            pos: Position::new(align, conf.index),
+            timeout: conf.timeout.map_or(interval, Duration::from_secs),
+            show_errors: conf.show_errors.unwrap_or(false),
+            timer: timer,
+            run: None,
+            output: None,
+            failure: None,
+        };
+        try!(exec.start(ev));
+        Ok(exec)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn position(&self) -> Position {
+        self.pos
+    }
+
+    /// Returns `true` if `event` came from one of this module's sources.
+    pub fn owns(&self, event: &Event) -> bool {
+        let token = event.token();
+        token == self.timer || self.run.as_ref().map_or(false, |run| {
+            run.deadline == token || run.stdout == Some(token) || run.stderr == Some(token)
+        })
+    }
+
+    /// Handles an event from one of this module's sources, returning `true` if a run finished.
+    pub fn handle(&mut self, ev: &mut EventLoop, event: Event) -> Result<bool> {
+        match event {
+            Event::Timer(t) if t == self.timer => {
+                try!(self.start(ev));
+                return Ok(false);
+            },
+            Event::Timer(..) => {
+                // Past the deadline.
+                if let Some(ref mut run) = self.run {
+                    if run.status.is_none() {
+                        kill(&mut run.child);
+                        run.err = vec![format!("timed out after {}s", self.timeout.as_secs())];
+                    }
+                }
+                try!(self.finish(ev));
+                return Ok(true);
+            },
+            Event::Line(t, line) => {
+                if let Some(ref mut run) = self.run {
+                    if run.stdout == Some(t) {
+                        run.out.push(line);
+                    } else {
+                        run.err.push(line);
+                    }
+                }
+            },
+            Event::Closed(t) => {
+                if let Some(ref mut run) = self.run {
+                    if run.stdout == Some(t) {
+                        run.stdout = None;
+                    } else if run.stderr == Some(t) {
+                        run.stderr = None;
+                    }
+                }
+            },
+            _ => {},
+        }
+        self.try_finish(ev)
+    }
+
+    /// Checks whether the command has exited, returning `true` if that finished the run.
+    ///
+    /// Should be called whenever a `SIGCHLD` arrives.
+    pub fn reap(&mut self, ev: &mut EventLoop) -> Result<bool> {
+        if let Some(ref mut run) = self.run {
+            if run.status.is_none() {
+                let pid = run.child.id() as libc::pid_t;
+                let mut status = 0;
+                if unsafe { libc::waitpid(pid, &mut status, libc::WNOHANG) } == pid {
+                    run.status = Some(status);
+                }
+            }
+        }
+        self.try_finish(ev)
+    }
+
+    /// Kills any run in progress and removes the module's sources from `ev`.
+    pub fn stop(&mut self, ev: &mut EventLoop) -> Result<()> {
+        if let Some(mut run) = self.run.take() {
+            if run.status.is_none() {
+                kill(&mut run.child);
+            }
+            for &token in run.stdout.iter().chain(run.stderr.iter()) {
+                try!(ev.remove(token));
+            }
+            try!(ev.remove(run.deadline));
+        }
+        ev.remove(self.timer)
+    }
+
+    fn start(&mut self, ev: &mut EventLoop) -> Result<()> {
+        // Let a slow run finish, or time out, rather than piling up.
+        if self.run.is_some() {
+            return Ok(());
+        }
+
+        let (out_rd, out_wr) = try!(pipe::pipe());
+        let (err_rd, err_wr) = try!(pipe::pipe());
+        let child = try!(Command::new("sh")
+                         .arg("-c")
+                         .arg(&self.cmd)
+                         .stdin(Stdio::null())
+                         .stdout(try!(out_wr.stdio()))
+                         .stderr(try!(err_wr.stdio()))
+                         // A group of its own, so whatever the command starts can be killed
+                         // along with it.
+                         .before_exec(|| {
+                             unsafe {
+                                 libc::setpgid(0, 0);
+                             }
+                             Ok(())
+                         })
+                         .spawn());
+        // Only the child may hold the write ends, or the pipes never report being closed.
+        drop(out_wr);
+        drop(err_wr);
+
+        self.run = Some(Run {
+            child: child,
+            status: None,
+            stdout: Some(try!(ev.add_pipe(out_rd))),
+            stderr: Some(try!(ev.add_pipe(err_rd))),
+            deadline: try!(ev.add_oneshot(self.timeout)),
+            out: Vec::new(),
+            err: Vec::new(),
+        });
+        Ok(())
+    }
+
+    /// Finishes the run if the child has exited and all of its output has been read.
+    fn try_finish(&mut self, ev: &mut EventLoop) -> Result<bool> {
+        let done = self.run.as_ref().map_or(false, |run| {
+            run.status.is_some() && run.stdout.is_none() && run.stderr.is_none()
+        });
+        if done {
+            try!(self.finish(ev));
+        }
+        Ok(done)
+    }
+
+    fn finish(&mut self, ev: &mut EventLoop) -> Result<()> {
+        let run = match self.run.take() {
+            Some(run) => run,
+            None => return Ok(()),
+        };
+        for &token in run.stdout.iter().chain(run.stderr.iter()) {
+            try!(ev.remove(token));
+        }
+        try!(ev.remove(run.deadline));
+
+        match run.status {
+            Some(0) => {
+                let out: Vec<&str> = run.out.iter()
+                    .map(|l| l.trim())
+                    .filter(|l| !l.is_empty())
+                    .collect();
+                self.output = Some(out.join(" "));
+                self.failure = None;
+            },
+            status => {
+                let reason = run.err.iter()
+                    .map(|l| l.trim())
+                    .find(|l| !l.is_empty())
+                    .map(String::from)
+                    .or_else(|| status.map(|s| format!("exited with status {}", (s >> 8) & 0xff)))
+                    .unwrap_or(String::from("failed"));
+                self.failure = Some(reason);
+            },
+        }
+        Ok(())
+    }
+}
+
+/// Kills a run's command along with anything it started, and reaps it.
+///
+/// Killing only the shell would leave its children holding the pipes open.
+fn kill(child: &mut Child) {
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+    let _ = child.wait();
+}
+
+impl Format for Exec {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<()> {
+        match self.failure {
+            Some(ref failure) if self.show_errors => {
+                let msg = text::escape(&format!("{}: {}", self.name, failure));
+                fmt.write(Styled::from(&*msg).set_style(Style::Critical))
+            },
+            _ => fmt.write(&*text::escape(self.output.as_ref().map_or("", |o| &**o))),
+        }
+    }
+}
+
+impl ToJson for Exec {
+    fn to_json(&self) -> Json {
+        let mut obj = BTreeMap::new();
+        obj.insert(String::from("output"), self.output.to_json());
+        obj.insert(String::from("failure"), self.failure.to_json());
+        obj.insert(String::from("running"), self.run.is_some().to_json());
+        Json::Object(obj)
+    }
+}
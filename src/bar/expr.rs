@@ -0,0 +1,882 @@
+//! A small expression language for deciding how a module is displayed.
+//!
+//! A program is a single expression evaluated against the module's data, the same values
+//! `bar state --json` reports. The fields of the data are in scope by name, and the whole of it
+//! as `data`:
+//!
+//! ```text
+//! if status == "full" then null
+//! else if pct < 15 then critical("bat " + pct + "%")
+//! else "bat " + zpad(pct, 3) + "%"
+//! ```
+//!
+//! The result is shown as text, except that `null` hides the module and the `warning`,
+//! `critical` and `normal` functions pick a style. There are no loops or user-defined functions,
+//! and every evaluation runs on a budget of steps, so a program can't hang the bar.
+
+use std::collections::BTreeMap;
+use std::cmp::Ordering;
+use std::iter::Peekable;
+use std::str::{
+    CharIndices,
+    FromStr,
+};
+use std::f64;
+
+use rustc_serialize::json::{
+    Json,
+    ToJson,
+};
+
+use bar::{
+    Format,
+    Formatter,
+    Style,
+    Styled,
+};
+use util::{
+    text,
+    Result,
+    Error,
+};
+
+/// Upper bound on widths passed to `pad` and `zpad`, so a program can't exhaust memory.
+const MAX_PAD: usize = 1024;
+
+/// A parsed program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    expr: Expr,
+}
+
+impl FromStr for Program {
+    type Err = Error;
+
+    fn from_str(src: &str) -> Result<Program> {
+        let tokens = try!(lex(src));
+        let mut parser = Parser {
+            tokens: tokens,
+            pos: 0,
+        };
+        let expr = try!(parser.expr());
+        if parser.peek() != &Tok::End {
+            return Err(parser.error("expected end of script"));
+        }
+        Ok(Program {
+            expr: expr,
+        })
+    }
+}
+
+impl Program {
+    /// Evaluates the program against `data`, giving up after `max_steps` steps.
+    pub fn eval(&self, data: &Json, max_steps: usize) -> Result<Output> {
+        let value = try!(self.value(data, max_steps));
+        Ok(Output::from_value(&value))
+    }
+
+    /// Evaluates the program against `data`, returning the raw value it results in.
+    pub fn value(&self, data: &Json, max_steps: usize) -> Result<Json> {
+        let mut eval = Eval {
+            data: data,
+            steps: max_steps,
+            max_steps: max_steps,
+        };
+        eval.eval(&self.expr)
+    }
+}
+
+/// What a program decided to display.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Output {
+    text: String,
+    style: Style,
+}
+
+impl Output {
+    fn from_value(value: &Json) -> Output {
+        match *value {
+            Json::Object(ref obj) if obj.contains_key("text") => {
+                let style = obj.get("style")
+                    .and_then(|s| s.as_string())
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(Style::Normal);
+                Output {
+                    text: to_text(&obj["text"]),
+                    style: style,
+                }
+            },
+            ref value => {
+                Output {
+                    text: to_text(value),
+                    style: Style::Normal,
+                }
+            },
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn style(&self) -> Style {
+        self.style
+    }
+}
+
+impl Format for Output {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<()> {
+        if self.text.is_empty() {
+            return Ok(());
+        }
+        let text = text::escape(&self.text);
+        if self.style == Style::Normal {
+            fmt.write(&*text)
+        } else {
+            fmt.write(Styled::from(&*text).set_style(self.style))
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Lit(Json),
+    Var(String),
+    List(Vec<Expr>),
+    Field(Box<Expr>, String),
+    Index(Box<Expr>, Box<Expr>),
+    Call(String, Vec<Expr>),
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    If(Box<Expr>, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+    Num(f64),
+    Str(String),
+    Ident(String),
+    Op(&'static str),
+    End,
+}
+
+/// Operators, longest first so `<=` isn't lexed as `<`.
+const OPS: &'static [&'static str] = &[
+    "==", "!=", "<=", ">=", "<", ">", "+", "-", "*", "/", "%", "(", ")", "[", "]", ",", ".",
+];
+
+/// Splits `src` into tokens, each with the column it starts at.
+fn lex(src: &str) -> Result<Vec<(Tok, usize)>> {
+    let mut tokens = Vec::new();
+    let mut chars = src.char_indices().peekable();
+
+    loop {
+        let (start, c) = match chars.peek() {
+            Some(&next) => next,
+            None => break,
+        };
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_digit(10) {
+            let end = take_while(&mut chars, start, |c| c.is_digit(10) || c == '.');
+            let num = try!(src[start..end].parse()
+                           .map_err(|_| syntax_error(start, "invalid number")));
+            tokens.push((Tok::Num(num), start));
+        } else if c == '"' || c == '\'' {
+            chars.next();
+            let mut s = String::new();
+            loop {
+                match chars.next() {
+                    Some((_, '\\')) => {
+                        match chars.next() {
+                            Some((_, 'n')) => s.push('\n'),
+                            Some((_, c)) => s.push(c),
+                            None => return Err(syntax_error(start, "unterminated string")),
+                        }
+                    },
+                    Some((_, end)) if end == c => break,
+                    Some((_, c)) => s.push(c),
+                    None => return Err(syntax_error(start, "unterminated string")),
+                }
+            }
+            tokens.push((Tok::Str(s), start));
+        } else if c.is_alphabetic() || c == '_' {
+            let end = take_while(&mut chars, start, |c| c.is_alphanumeric() || c == '_');
+            tokens.push((Tok::Ident(String::from(&src[start..end])), start));
+        } else {
+            let op = match OPS.iter().find(|op| src[start..].starts_with(**op)) {
+                Some(op) => *op,
+                None => return Err(syntax_error(start, &format!("unexpected `{}`", c))),
+            };
+            for _ in 0..op.len() {
+                chars.next();
+            }
+            tokens.push((Tok::Op(op), start));
+        }
+    }
+    tokens.push((Tok::End, src.len()));
+    Ok(tokens)
+}
+
+/// Advances `chars` past the characters matching `pred`, returning the byte offset of the first
+/// one that doesn't.
+fn take_while<F>(chars: &mut Peekable<CharIndices>, start: usize, pred: F) -> usize
+    where F: Fn(char) -> bool
+{
+    let mut end = start;
+    loop {
+        let (i, c) = match chars.peek() {
+            Some(&next) => next,
+            None => return end,
+        };
+        if !pred(c) {
+            return end;
+        }
+        end = i + c.len_utf8();
+        chars.next();
+    }
+}
+
+fn syntax_error(col: usize, msg: &str) -> Error {
+    Error::new(format!("syntax error at column {}: {}", col + 1, msg))
+}
+
+struct Parser {
+    tokens: Vec<(Tok, usize)>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Tok {
+        &self.tokens[self.pos].0
+    }
+
+    fn next(&mut self) -> Tok {
+        let tok = self.tokens[self.pos].0.clone();
+        if tok != Tok::End {
+            self.pos += 1;
+        }
+        tok
+    }
+
+    fn error(&self, msg: &str) -> Error {
+        syntax_error(self.tokens[self.pos].1, msg)
+    }
+
+    /// Consumes the next token if it is the keyword or operator `s`.
+    fn eat(&mut self, s: &str) -> bool {
+        let found = match *self.peek() {
+            Tok::Op(op) => op == s,
+            Tok::Ident(ref id) => id == s,
+            _ => false,
+        };
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, s: &str) -> Result<()> {
+        if self.eat(s) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected `{}`", s)))
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr> {
+        if self.eat("if") {
+            let cond = try!(self.expr());
+            try!(self.expect("then"));
+            let then = try!(self.expr());
+            try!(self.expect("else"));
+            let els = try!(self.expr());
+            return Ok(Expr::If(Box::new(cond), Box::new(then), Box::new(els)));
+        }
+        self.or()
+    }
+
+    fn or(&mut self) -> Result<Expr> {
+        let mut lhs = try!(self.and());
+        while self.eat("or") {
+            let rhs = try!(self.and());
+            lhs = Expr::Or(Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn and(&mut self) -> Result<Expr> {
+        let mut lhs = try!(self.not());
+        while self.eat("and") {
+            let rhs = try!(self.not());
+            lhs = Expr::And(Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn not(&mut self) -> Result<Expr> {
+        if self.eat("not") {
+            let inner = try!(self.not());
+            return Ok(Expr::Not(Box::new(inner)));
+        }
+        self.cmp()
+    }
+
+    fn cmp(&mut self) -> Result<Expr> {
+        let lhs = try!(self.add());
+        let op = match *self.peek() {
+            Tok::Op("==") => BinOp::Eq,
+            Tok::Op("!=") => BinOp::Ne,
+            Tok::Op("<") => BinOp::Lt,
+            Tok::Op("<=") => BinOp::Le,
+            Tok::Op(">") => BinOp::Gt,
+            Tok::Op(">=") => BinOp::Ge,
+            _ => return Ok(lhs),
+        };
+        self.next();
+        let rhs = try!(self.add());
+        Ok(Expr::Binary(op, Box::new(lhs), Box::new(rhs)))
+    }
+
+    fn add(&mut self) -> Result<Expr> {
+        let mut lhs = try!(self.mul());
+        loop {
+            let op = match *self.peek() {
+                Tok::Op("+") => BinOp::Add,
+                Tok::Op("-") => BinOp::Sub,
+                _ => return Ok(lhs),
+            };
+            self.next();
+            let rhs = try!(self.mul());
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    fn mul(&mut self) -> Result<Expr> {
+        let mut lhs = try!(self.unary());
+        loop {
+            let op = match *self.peek() {
+                Tok::Op("*") => BinOp::Mul,
+                Tok::Op("/") => BinOp::Div,
+                Tok::Op("%") => BinOp::Rem,
+                _ => return Ok(lhs),
+            };
+            self.next();
+            let rhs = try!(self.unary());
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        if self.eat("-") {
+            let inner = try!(self.unary());
+            return Ok(Expr::Neg(Box::new(inner)));
+        }
+        self.postfix()
+    }
+
+    fn postfix(&mut self) -> Result<Expr> {
+        let mut expr = try!(self.primary());
+        loop {
+            if self.eat(".") {
+                match self.next() {
+                    Tok::Ident(field) => expr = Expr::Field(Box::new(expr), field),
+                    _ => return Err(self.error("expected a field name")),
+                }
+            } else if self.eat("[") {
+                let idx = try!(self.expr());
+                try!(self.expect("]"));
+                expr = Expr::Index(Box::new(expr), Box::new(idx));
+            } else {
+                return Ok(expr);
+            }
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr> {
+        match self.next() {
+            Tok::Num(n) => Ok(Expr::Lit(Json::F64(n))),
+            Tok::Str(s) => Ok(Expr::Lit(Json::String(s))),
+            Tok::Ident(id) => {
+                match &*id {
+                    "true" => return Ok(Expr::Lit(Json::Boolean(true))),
+                    "false" => return Ok(Expr::Lit(Json::Boolean(false))),
+                    "null" => return Ok(Expr::Lit(Json::Null)),
+                    "if" | "then" | "else" | "and" | "or" | "not" => {
+                        self.pos -= 1;
+                        return Err(self.error(&format!("unexpected `{}`", id)));
+                    },
+                    _ => {},
+                }
+                if self.eat("(") {
+                    let args = try!(self.list(")"));
+                    Ok(Expr::Call(id, args))
+                } else {
+                    Ok(Expr::Var(id))
+                }
+            },
+            Tok::Op("(") => {
+                let expr = try!(self.expr());
+                try!(self.expect(")"));
+                Ok(expr)
+            },
+            Tok::Op("[") => {
+                let items = try!(self.list("]"));
+                Ok(Expr::List(items))
+            },
+            Tok::End => Err(self.error("unexpected end of script")),
+            _ => {
+                self.pos -= 1;
+                Err(self.error("expected a value"))
+            },
+        }
+    }
+
+    /// Parses comma-separated expressions up to and including `close`.
+    fn list(&mut self, close: &str) -> Result<Vec<Expr>> {
+        let mut items = Vec::new();
+        if self.eat(close) {
+            return Ok(items);
+        }
+        loop {
+            items.push(try!(self.expr()));
+            if self.eat(close) {
+                return Ok(items);
+            }
+            try!(self.expect(","));
+        }
+    }
+}
+
+struct Eval<'a> {
+    data: &'a Json,
+    steps: usize,
+    max_steps: usize,
+}
+
+impl<'a> Eval<'a> {
+    fn step(&mut self) -> Result<()> {
+        if self.steps == 0 {
+            return Err(Error::new(format!("script exceeded its budget of {} steps",
+                                          self.max_steps)));
+        }
+        self.steps -= 1;
+        Ok(())
+    }
+
+    fn eval(&mut self, expr: &Expr) -> Result<Json> {
+        try!(self.step());
+        match *expr {
+            Expr::Lit(ref value) => Ok(value.clone()),
+            Expr::Var(ref name) => {
+                if name == "data" {
+                    return Ok(self.data.clone());
+                }
+                match self.data.find(name) {
+                    Some(value) => Ok(value.clone()),
+                    None => Err(Error::new(format!("unknown variable `{}`", name))),
+                }
+            },
+            Expr::List(ref items) => {
+                let mut values = Vec::new();
+                for item in items {
+                    values.push(try!(self.eval(item)));
+                }
+                Ok(Json::Array(values))
+            },
+            Expr::Field(ref inner, ref field) => {
+                let value = try!(self.eval(inner));
+                Ok(value.find(field).cloned().unwrap_or(Json::Null))
+            },
+            Expr::Index(ref inner, ref idx) => {
+                let value = try!(self.eval(inner));
+                let idx = try!(self.eval(idx));
+                index(&value, &idx)
+            },
+            Expr::Call(ref name, ref args) => {
+                let mut values = Vec::new();
+                for arg in args {
+                    values.push(try!(self.eval(arg)));
+                }
+                call(name, &values)
+            },
+            Expr::Neg(ref inner) => {
+                let value = try!(self.eval(inner));
+                Ok(Json::F64(-try!(to_num(&value))))
+            },
+            Expr::Not(ref inner) => {
+                let value = try!(self.eval(inner));
+                Ok(Json::Boolean(!truthy(&value)))
+            },
+            Expr::And(ref lhs, ref rhs) => {
+                let lhs = try!(self.eval(lhs));
+                if truthy(&lhs) { self.eval(rhs) } else { Ok(lhs) }
+            },
+            Expr::Or(ref lhs, ref rhs) => {
+                let lhs = try!(self.eval(lhs));
+                if truthy(&lhs) { Ok(lhs) } else { self.eval(rhs) }
+            },
+            Expr::Binary(op, ref lhs, ref rhs) => {
+                let lhs = try!(self.eval(lhs));
+                let rhs = try!(self.eval(rhs));
+                binary(op, &lhs, &rhs)
+            },
+            Expr::If(ref cond, ref then, ref els) => {
+                let cond = try!(self.eval(cond));
+                if truthy(&cond) { self.eval(then) } else { self.eval(els) }
+            },
+        }
+    }
+}
+
+fn binary(op: BinOp, lhs: &Json, rhs: &Json) -> Result<Json> {
+    match op {
+        BinOp::Add if lhs.is_string() || rhs.is_string() => {
+            Ok(Json::String(to_text(lhs) + &to_text(rhs)))
+        },
+        BinOp::Eq => Ok(Json::Boolean(equal(lhs, rhs))),
+        BinOp::Ne => Ok(Json::Boolean(!equal(lhs, rhs))),
+        BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge => {
+            let ord = match (lhs.as_string(), rhs.as_string()) {
+                (Some(l), Some(r)) => l.cmp(r),
+                _ => {
+                    let (l, r) = (try!(to_num(lhs)), try!(to_num(rhs)));
+                    try!(l.partial_cmp(&r).ok_or(Error::new("cannot compare NaN")))
+                },
+            };
+            Ok(Json::Boolean(match op {
+                BinOp::Lt => ord == Ordering::Less,
+                BinOp::Le => ord != Ordering::Greater,
+                BinOp::Gt => ord == Ordering::Greater,
+                _ => ord != Ordering::Less,
+            }))
+        },
+        _ => {
+            let (l, r) = (try!(to_num(lhs)), try!(to_num(rhs)));
+            let n = match op {
+                BinOp::Add => l + r,
+                BinOp::Sub => l - r,
+                BinOp::Mul => l * r,
+                BinOp::Div | BinOp::Rem if r == 0.0 => return Err(Error::new("division by zero")),
+                BinOp::Div => l / r,
+                _ => l % r,
+            };
+            Ok(Json::F64(n))
+        },
+    }
+}
+
+fn index(value: &Json, idx: &Json) -> Result<Json> {
+    match *value {
+        Json::Array(ref items) => {
+            let i = try!(to_num(idx)) as isize;
+            // Negative indices count from the end.
+            let i = if i < 0 { items.len() as isize + i } else { i };
+            if i < 0 {
+                return Ok(Json::Null);
+            }
+            Ok(items.get(i as usize).cloned().unwrap_or(Json::Null))
+        },
+        Json::Object(ref obj) => Ok(obj.get(&to_text(idx)).cloned().unwrap_or(Json::Null)),
+        Json::Null => Ok(Json::Null),
+        _ => Err(Error::new(format!("cannot index `{}`", value))),
+    }
+}
+
+fn call(name: &str, args: &[Json]) -> Result<Json> {
+    let arg = |i| nth_arg(name, args, i);
+    let value = match name {
+        "len" => {
+            let n = match *try!(arg(0)) {
+                Json::String(ref s) => s.chars().count(),
+                Json::Array(ref a) => a.len(),
+                Json::Object(ref o) => o.len(),
+                Json::Null => 0,
+                ref v => return Err(Error::new(format!("`len` of `{}`", v))),
+            };
+            Json::F64(n as f64)
+        },
+        "str" => Json::String(to_text(try!(arg(0)))),
+        "num" => Json::F64(try!(to_num(try!(arg(0))))),
+        "round" => {
+            let n = try!(to_num(try!(arg(0))));
+            let digits = match args.get(1) {
+                Some(d) => try!(to_num(d)).max(0.0).min(15.0) as i32,
+                None => 0,
+            };
+            let scale = 10f64.powi(digits);
+            Json::F64((n * scale).round() / scale)
+        },
+        "floor" => Json::F64(try!(to_num(try!(arg(0)))).floor()),
+        "ceil" => Json::F64(try!(to_num(try!(arg(0)))).ceil()),
+        "abs" => Json::F64(try!(to_num(try!(arg(0)))).abs()),
+        "min" | "max" | "sum" | "avg" => {
+            let nums = try!(numbers(args));
+            if nums.is_empty() {
+                return Ok(Json::Null);
+            }
+            let n = match name {
+                "min" => nums.iter().fold(f64::INFINITY, |a, &b| a.min(b)),
+                "max" => nums.iter().fold(f64::NEG_INFINITY, |a, &b| a.max(b)),
+                "sum" => nums.iter().fold(0.0, |a, &b| a + b),
+                _ => nums.iter().fold(0.0, |a, &b| a + b) / nums.len() as f64,
+            };
+            Json::F64(n)
+        },
+        "pad" | "zpad" => {
+            let s = to_text(try!(arg(0)));
+            let width = try!(to_num(try!(arg(1)))).max(0.0) as usize;
+            if width > MAX_PAD {
+                return Err(Error::new(format!("`{}` width is over {}", name, MAX_PAD)));
+            }
+            let fill = if name == "pad" { ' ' } else { '0' };
+            let len = s.chars().count();
+            let mut padded: String = (len..width).map(|_| fill).collect();
+            padded.push_str(&s);
+            Json::String(padded)
+        },
+        "upper" => Json::String(to_text(try!(arg(0))).to_uppercase()),
+        "lower" => Json::String(to_text(try!(arg(0))).to_lowercase()),
+        "join" => {
+            let sep = args.get(1).map(to_text).unwrap_or(String::new());
+            match *try!(arg(0)) {
+                Json::Array(ref items) => {
+                    let texts: Vec<String> = items.iter().map(to_text).collect();
+                    Json::String(texts.join(&sep))
+                },
+                ref v => Json::String(to_text(v)),
+            }
+        },
+        "contains" => {
+            let needle = try!(arg(1));
+            let found = match *try!(arg(0)) {
+                Json::String(ref s) => s.contains(&*to_text(needle)),
+                Json::Array(ref items) => items.iter().any(|i| equal(i, needle)),
+                Json::Object(ref obj) => obj.contains_key(&to_text(needle)),
+                _ => false,
+            };
+            Json::Boolean(found)
+        },
+        "normal" | "warning" | "critical" => {
+            let mut obj = BTreeMap::new();
+            obj.insert(String::from("text"), Json::String(to_text(try!(arg(0)))));
+            obj.insert(String::from("style"), name.to_json());
+            Json::Object(obj)
+        },
+        _ => return Err(Error::new(format!("unknown function `{}`", name))),
+    };
+    Ok(value)
+}
+
+fn nth_arg<'a>(name: &str, args: &'a [Json], i: usize) -> Result<&'a Json> {
+    args.get(i).ok_or(Error::new(format!("`{}` takes at least {} argument(s)", name, i + 1)))
+}
+
+/// Flattens `args`, and any arrays among them, into numbers.
+fn numbers(args: &[Json]) -> Result<Vec<f64>> {
+    let mut nums = Vec::new();
+    for arg in args {
+        match *arg {
+            Json::Array(ref items) => {
+                for item in items {
+                    nums.push(try!(to_num(item)));
+                }
+            },
+            ref v => nums.push(try!(to_num(v))),
+        }
+    }
+    Ok(nums)
+}
+
+/// Returns `true` unless `value` is `null`, `false`, zero or empty.
+pub fn truthy(value: &Json) -> bool {
+    match *value {
+        Json::Null => false,
+        Json::Boolean(b) => b,
+        Json::I64(n) => n != 0,
+        Json::U64(n) => n != 0,
+        Json::F64(n) => n != 0.0,
+        Json::String(ref s) => !s.is_empty(),
+        Json::Array(ref a) => !a.is_empty(),
+        Json::Object(..) => true,
+    }
+}
+
+fn equal(lhs: &Json, rhs: &Json) -> bool {
+    match (lhs.as_f64(), rhs.as_f64()) {
+        (Some(l), Some(r)) => l == r,
+        _ => lhs == rhs,
+    }
+}
+
+fn to_num(value: &Json) -> Result<f64> {
+    match *value {
+        Json::Boolean(b) => Ok(if b { 1.0 } else { 0.0 }),
+        Json::String(ref s) => {
+            s.trim().parse().map_err(|_| Error::new(format!("`{}` is not a number", s)))
+        },
+        ref v => v.as_f64().ok_or(Error::new(format!("`{}` is not a number", v))),
+    }
+}
+
+/// Returns `value` as it is shown on the bar.
+pub fn to_text(value: &Json) -> String {
+    match *value {
+        Json::Null => String::new(),
+        Json::String(ref s) => s.clone(),
+        Json::F64(n) if n.fract() == 0.0 && n.abs() < 1e15 => format!("{}", n as i64),
+        Json::Array(ref items) => {
+            let texts: Vec<String> = items.iter().map(to_text).collect();
+            texts.join(",")
+        },
+        ref v => v.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rustc_serialize::json::Json;
+
+    use bar::{
+        Bar,
+        Position,
+        Style,
+    };
+    use super::{
+        Program,
+        to_text,
+        truthy,
+    };
+
+    fn data() -> Json {
+        Json::from_str(r#"{"pct": 12, "status": "discharging", "load": [1, 2, 3], "none": null}"#)
+            .unwrap()
+    }
+
+    fn value(src: &str) -> Json {
+        let program: Program = src.parse().unwrap();
+        program.value(&data(), 1000).unwrap()
+    }
+
+    fn error(src: &str) -> String {
+        match src.parse::<Program>() {
+            Ok(program) => program.value(&data(), 1000).unwrap_err().to_string(),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(value("1 + 2 * 3"), Json::F64(7.0));
+        assert_eq!(value("(1 + 2) * 3"), Json::F64(9.0));
+        assert_eq!(value("10 - 4 - 3"), Json::F64(3.0));
+        assert_eq!(value("-2 * 3 + 7 % 4"), Json::F64(-3.0));
+        assert_eq!(value("1 + 2 == 3"), Json::Boolean(true));
+        assert_eq!(value("not 1 == 2 and 3 < 4"), Json::Boolean(true));
+        assert_eq!(value("false and false or true"), Json::Boolean(true));
+        assert_eq!(value("true or false and false"), Json::Boolean(true));
+        assert_eq!(value("\"a\" + 1 + 2"), Json::String("a12".to_string()));
+        assert_eq!(value("if pct < 15 then \"low\" else \"ok\" + \"!\""),
+                   Json::String("low".to_string()));
+        assert_eq!(value("load[-1] * 2"), Json::F64(6.0));
+    }
+
+    #[test]
+    fn error_positions() {
+        assert_eq!(error("1 +"), "syntax error at column 4: unexpected end of script");
+        assert_eq!(error("pct $ 2"), "syntax error at column 5: unexpected `$`");
+        assert_eq!(error("\"abc"), "syntax error at column 1: unterminated string");
+        assert_eq!(error("(1 + 2"), "syntax error at column 7: expected `)`");
+        assert_eq!(error("1 2"), "syntax error at column 3: expected end of script");
+        assert_eq!(error("if pct then 1"), "syntax error at column 14: expected `else`");
+        assert_eq!(error("1 + then"), "syntax error at column 5: unexpected `then`");
+        assert_eq!(error("missing + 1"), "unknown variable `missing`");
+        assert_eq!(error("1 / 0"), "division by zero");
+    }
+
+    #[test]
+    fn budget() {
+        let program: Program = "1 + 2 * 3".parse().unwrap();
+        assert!(program.value(&data(), 5).is_ok());
+        assert_eq!(program.value(&data(), 4).unwrap_err().to_string(),
+                   "script exceeded its budget of 4 steps");
+        assert!(program.value(&data(), 0).is_err());
+
+        // Branches that aren't taken don't count.
+        let program: Program = "if true then 1 else [1, 2, 3, 4, 5, 6]".parse().unwrap();
+        assert!(program.value(&data(), 3).is_ok());
+    }
+
+    #[test]
+    fn null_and_truthy() {
+        assert!(!truthy(&Json::Null));
+        assert!(!truthy(&Json::Boolean(false)));
+        assert!(!truthy(&Json::F64(0.0)));
+        assert!(!truthy(&Json::String(String::new())));
+        assert!(!truthy(&Json::Array(vec![])));
+        assert!(truthy(&Json::F64(0.5)));
+        assert!(truthy(&Json::String("0".to_string())));
+        assert!(truthy(&data()));
+
+        assert_eq!(value("none"), Json::Null);
+        assert_eq!(value("data.missing"), Json::Null);
+        assert_eq!(value("none[0]"), Json::Null);
+        assert_eq!(value("load[5]"), Json::Null);
+        assert_eq!(value("none or \"fallback\""), Json::String("fallback".to_string()));
+        assert_eq!(value("none and missing"), Json::Null);
+        assert_eq!(value("len(none)"), Json::F64(0.0));
+        assert_eq!(value("none == null"), Json::Boolean(true));
+        assert_eq!(value("pct == \"12\""), Json::Boolean(false));
+
+        assert_eq!(to_text(&Json::Null), "");
+        assert_eq!(to_text(&Json::F64(3.0)), "3");
+        assert_eq!(to_text(&Json::F64(2.5)), "2.5");
+        assert_eq!(to_text(&value("load")), "1,2,3");
+    }
+
+    /// Returns what `src` shows on the bar.
+    fn rendered(src: &str) -> String {
+        let program: Program = src.parse().unwrap();
+        let mut bar = Bar::new();
+        bar.register(Position::left(), &program.eval(&data(), 1000).unwrap());
+        bar.rendered(Position::left()).unwrap()
+    }
+
+    #[test]
+    fn output() {
+        let program: Program = "if status == \"full\" then null else critical(pct + \"%\")"
+            .parse().unwrap();
+        let output = program.eval(&data(), 1000).unwrap();
+        assert_eq!(output.text(), "12%");
+        assert_eq!(output.style(), Style::Critical);
+
+        let program: Program = "null".parse().unwrap();
+        let output = program.eval(&data(), 1000).unwrap();
+        assert_eq!(output.text(), "");
+        assert_eq!(output.style(), Style::Normal);
+
+        assert_eq!(rendered("critical(pct + \"%\")"), "%{F#fdf6e3 B#dc322f}12%%%{F- B-}");
+        assert_eq!(rendered("warning(\"%{F-}\")"), "%{F#b58900 B-}%%{F-}%{F- B-}");
+        assert_eq!(rendered("normal(status)"), "discharging");
+        assert_eq!(rendered("\"bat \" + pct + \"%\""), "bat 12%%");
+        assert_eq!(rendered("null"), "");
+    }
+}
@@ -0,0 +1,122 @@
+use std::process::{
+    Command,
+    Stdio,
+};
+use std::collections::BTreeMap;
+
+use rustc_serialize::json::Json;
+
+use expr::{
+    self,
+    Program,
+};
+use util::{
+    self,
+    Result,
+};
+
+/// Budget of a hook's expressions, unless the config says otherwise.
+const MAX_STEPS: usize = 1000;
+
+/// Runs a command when a value derived from a module's data changes.
+///
+/// The watched value is the result of an expression evaluated against the module's data, or the
+/// whole of the data. Once it changes, an optional condition is evaluated with the previous and
+/// current values in scope as `old` and `new`, and the command is run if it holds. The first
+/// value seen is only remembered, since there is nothing to compare it to.
+#[derive(Debug)]
+pub struct Hook {
+    module: String,
+    value: Option<Program>,
+    condition: Option<Program>,
+    cmd: String,
+    max_steps: usize,
+    last: Option<Json>,
+    error: Option<String>,
+}
+
+impl Hook {
+    pub fn new(conf: &util::Hook) -> Result<Hook> {
+        let value = match conf.value {
+            Some(ref value) => Some(try!(value.parse())),
+            None => None,
+        };
+        let condition = match conf.condition {
+            Some(ref condition) => Some(try!(condition.parse())),
+            None => None,
+        };
+
+        Ok(Hook {
+            module: conf.module.clone(),
+            value: value,
+            condition: condition,
+            cmd: conf.command.clone(),
+            max_steps: conf.max_steps.unwrap_or(MAX_STEPS),
+            last: None,
+            error: None,
+        })
+    }
+
+    /// Name of the module whose data is watched.
+    pub fn module(&self) -> &str {
+        &self.module
+    }
+
+    /// Checks the module's current data, returning the command to run if the hook fires.
+    ///
+    /// Errors are reported once, rather than every time the data is checked.
+    pub fn check(&mut self, data: &Json) -> Option<Command> {
+        match self.try_check(data) {
+            Ok(cmd) => {
+                self.error = None;
+                cmd
+            },
+            Err(err) => {
+                let msg = format!("hook on `{}`: {}", self.module, err);
+                if self.error.as_ref() != Some(&msg) {
+                    ::log_error(&msg);
+                }
+                self.error = Some(msg);
+                None
+            },
+        }
+    }
+
+    fn try_check(&mut self, data: &Json) -> Result<Option<Command>> {
+        let new = match self.value {
+            Some(ref value) => try!(value.value(data, self.max_steps)),
+            None => data.clone(),
+        };
+        let old = match self.last.take() {
+            Some(old) => old,
+            None => {
+                self.last = Some(new);
+                return Ok(None);
+            },
+        };
+        self.last = Some(new.clone());
+        if old == new {
+            return Ok(None);
+        }
+
+        if let Some(ref condition) = self.condition {
+            let mut scope = BTreeMap::new();
+            scope.insert(String::from("old"), old.clone());
+            scope.insert(String::from("new"), new.clone());
+            if !expr::truthy(&try!(condition.value(&Json::Object(scope), self.max_steps))) {
+                return Ok(None);
+            }
+        }
+
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c")
+           .arg(&self.cmd)
+           .env("BAR_MODULE", &self.module)
+           .env("BAR_OLD", expr::to_text(&old))
+           .env("BAR_NEW", expr::to_text(&new))
+           .stdin(Stdio::null())
+           .stdout(Stdio::null())
+           .stderr(Stdio::inherit());
+        Ok(Some(cmd))
+    }
+}
@@ -2,6 +2,7 @@
 #![feature(associated_consts)]
 #![feature(collections, collections_bound, btree_range)]
 #![feature(unicode)]
+#![feature(process_exec)]
 
 extern crate libc;
 extern crate docopt;
@@ -9,6 +10,8 @@ extern crate toml;
 extern crate term;
 extern crate rustc_serialize;
 extern crate collections;
+extern crate unicode_segmentation;
+extern crate unicode_width;
 
 mod bar;
 pub use self::bar::Bar;
@@ -17,17 +20,38 @@ pub use self::bar::{
     Formatter,
     Position,
     Align,
+    Color,
+    Layout,
+    Marquee,
+    Decorations,
+    Ends,
+    Separator,
+    Style,
+    Styled,
 };
 pub mod data;
 pub mod util;
 pub mod pipe;
+pub mod event;
+pub mod supervisor;
+pub mod control;
+pub mod title;
+pub mod script;
+pub mod exec;
+pub mod tail;
+pub mod backlight;
+pub mod mpd;
+pub mod alert;
+pub mod hook;
+pub mod plugin;
+pub mod expr;
+pub mod sys;
+
+use std::fmt;
 
 use term::color;
 use term::Terminal;
-use rustc_serialize::{
-    Decodable,
-    Encodable,
-};
+use rustc_serialize::Decodable;
 use docopt::Docopt;
 
 use util::{
@@ -35,13 +59,14 @@ use util::{
     Result,
 };
 
-pub fn execute<A, V>(exec: fn(A) -> Result<V>, usage: &str)
-    where A: Decodable,
-          V: Encodable,
+/// Runs `exec` with arguments decoded according to `usage`, exiting with the status code it
+/// returns.
+pub fn execute<A>(exec: fn(A) -> Result<i32>, usage: &str) -> !
+    where A: Decodable
 {
     let args: A = decode_args(usage);
     match (exec)(args) {
-        Ok(..) => {},
+        Ok(code) => ::std::process::exit(code),
         Err(e) => handle_error(e),
     }
 }
@@ -56,12 +81,19 @@ fn decode_args<A>(usage: &str) -> A
     docopt.decode().unwrap_or_else(|e| e.exit())
 }
 
-fn handle_error(err: Error) -> ! {
+/// Prints `err` to stderr without exiting.
+pub fn log_error<E>(err: &E)
+    where E: fmt::Display
+{
     if let Some(mut t) = term::stderr() {
         let _ = t.fg(color::RED);
         let _ = writeln!(t, "{}", err);
         let _ = t.reset();
     }
+}
+
+fn handle_error(err: Error) -> ! {
+    log_error(&err);
     ::std::process::exit(1)
 }
 
@@ -0,0 +1,803 @@
+//! A module showing what MPD is playing.
+//!
+//! MPD is asked for its status and the current song, then told to `idle` until the player or
+//! mixer changes, at which point it is asked again. Commands sent on click first cancel the
+//! `idle` with `noidle`, since MPD refuses anything else while idling. Requests are pipelined,
+//! and each response, ended by `OK` or `ACK ...`, answers the oldest one outstanding.
+
+use std::collections::{
+    BTreeMap,
+    VecDeque,
+};
+use std::io::prelude::*;
+use std::io::{
+    self,
+    ErrorKind,
+};
+use std::net::{
+    SocketAddr,
+    TcpStream,
+    ToSocketAddrs,
+};
+use std::os::unix::net::UnixStream;
+use std::os::unix::io::{
+    AsRawFd,
+    FromRawFd,
+    RawFd,
+};
+use std::time::{
+    Duration,
+    Instant,
+};
+use std::env;
+use std::mem;
+use libc;
+
+use rustc_serialize::json::{
+    Json,
+    ToJson,
+};
+
+use bar::{
+    Align,
+    Format,
+    Formatter,
+    Position,
+};
+use event::{
+    Event,
+    EventLoop,
+    Token,
+};
+use util::{
+    self,
+    text,
+    Result,
+    Error,
+};
+
+/// Seconds to wait before reconnecting to MPD.
+const RECONNECT: u64 = 5;
+
+/// Milliseconds to wait for each address of a TCP connection, since the bar waits with it.
+const CONNECT_TIMEOUT: libc::c_int = 500;
+
+const PLAYING: char = '\u{25b6}';
+const PAUSED: char = '\u{23f8}';
+
+/// A connection to MPD.
+#[derive(Debug)]
+enum Conn {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Conn {
+    /// Connects to `addr`, a socket path if it starts with `/`, or `host:port` otherwise.
+    fn connect(addr: &str) -> io::Result<Conn> {
+        let conn = if addr.starts_with('/') {
+            let stream = try!(UnixStream::connect(addr));
+            try!(stream.set_nonblocking(true));
+            Conn::Unix(stream)
+        } else {
+            let stream = try!(connect_tcp(addr, CONNECT_TIMEOUT));
+            try!(stream.set_nonblocking(true));
+            Conn::Tcp(stream)
+        };
+        Ok(conn)
+    }
+}
+
+impl Read for Conn {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            Conn::Tcp(ref mut s) => s.read(buf),
+            Conn::Unix(ref mut s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Conn {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Conn::Tcp(ref mut s) => s.write(buf),
+            Conn::Unix(ref mut s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Conn::Tcp(ref mut s) => s.flush(),
+            Conn::Unix(ref mut s) => s.flush(),
+        }
+    }
+}
+
+impl AsRawFd for Conn {
+    fn as_raw_fd(&self) -> RawFd {
+        match *self {
+            Conn::Tcp(ref s) => s.as_raw_fd(),
+            Conn::Unix(ref s) => s.as_raw_fd(),
+        }
+    }
+}
+
+/// A request whose response hasn't been read yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Request {
+    /// Not a request, but MPD greets every connection with `OK MPD <version>`.
+    Greeting,
+    Status,
+    CurrentSong,
+    Idle,
+    /// A command sent on click, whose response carries nothing of interest.
+    Command,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Play,
+    Pause,
+    Stop,
+}
+
+/// A connection along with the requests outstanding on it.
+#[derive(Debug)]
+struct Session {
+    conn: Conn,
+    src: Token,
+    buf: Vec<u8>,
+    pending: VecDeque<Request>,
+    /// Key-value pairs of the response being read.
+    fields: Vec<(String, String)>,
+    /// Commands to send once the `idle` in progress has been cancelled.
+    queued: Vec<&'static str>,
+    cancelled: bool,
+}
+
+/// A module showing the song MPD is playing, controlled by clicking on it.
+///
+/// A left click toggles pause, a middle click skips to the previous song and a right click to
+/// the next one.
+#[derive(Debug)]
+pub struct Mpd {
+    name: String,
+    addr: String,
+    pos: Position,
+    max_width: Option<usize>,
+    session: Option<Session>,
+    /// Retries the connection while there is none.
+    retry: Option<Token>,
+    state: State,
+    artist: String,
+    title: String,
+    /// Seconds played when the status was read, and when that was.
+    elapsed: (f64, Instant),
+    duration: Option<f64>,
+    volume: Option<i64>,
+    error: Option<String>,
+}
+
+impl Mpd {
+    /// Creates the module and connects to MPD. Failing to connect isn't fatal, the connection
+    /// is just retried later.
+    pub fn new(conf: &util::Mpd, ev: &mut EventLoop) -> Result<Mpd> {
+        let align: Align = try!(conf.align.parse());
+        let addr = conf.address.clone().unwrap_or_else(default_addr);
+
+        let mut mpd = Mpd {
+            name: conf.name.clone(),
+            addr: addr,
+            pos: Position::new(align, conf.index),
+            max_width: conf.max_width,
+            session: None,
+            retry: None,
+            state: State::Stop,
+            artist: String::new(),
+            title: String::new(),
+            elapsed: (0.0, Instant::now()),
+            duration: None,
+            volume: None,
+            error: None,
+        };
+        try!(mpd.connect(ev));
+        Ok(mpd)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn position(&self) -> Position {
+        self.pos
+    }
+
+    /// Returns `true` if `event` came from one of this module's sources.
+    pub fn owns(&self, event: &Event) -> bool {
+        let token = event.token();
+        self.retry == Some(token) || self.session.as_ref().map_or(false, |s| s.src == token)
+    }
+
+    /// Handles an event from one of this module's sources, returning `true` if what is shown
+    /// changed.
+    pub fn handle(&mut self, ev: &mut EventLoop, event: Event) -> Result<bool> {
+        match event {
+            Event::Timer(t) => {
+                try!(ev.remove(t));
+                self.retry = None;
+                try!(self.connect(ev));
+                Ok(self.session.is_some())
+            },
+            Event::Ready(..) => {
+                match self.receive() {
+                    Ok(changed) => Ok(changed),
+                    Err(err) => {
+                        try!(self.disconnect(ev, err));
+                        Ok(true)
+                    },
+                }
+            },
+            _ => Ok(false),
+        }
+    }
+
+    /// Returns `true` while the elapsed time shown is ticking.
+    pub fn tick(&self) -> bool {
+        self.state == State::Play
+    }
+
+    /// Returns `true` if `action` is one `lemonbar` reports for clicks on this module.
+    pub fn owns_action(&self, action: &str) -> bool {
+        self.command(action).is_some()
+    }
+
+    /// Sends the command behind a click.
+    pub fn click(&mut self, ev: &mut EventLoop, action: &str) -> Result<()> {
+        let cmd = match self.command(action) {
+            Some(cmd) => cmd,
+            None => return Ok(()),
+        };
+        let res = match self.session {
+            Some(ref mut session) => {
+                session.queued.push(cmd);
+                if session.cancelled {
+                    Ok(())
+                } else {
+                    session.cancelled = true;
+                    session.conn.write_all(b"noidle\n")
+                }
+            },
+            None => Ok(()),
+        };
+        if let Err(err) = res {
+            try!(self.disconnect(ev, Error::from(err)));
+        }
+        Ok(())
+    }
+
+    /// Closes the connection, if any, and removes the module's sources from `ev`.
+    pub fn stop(&mut self, ev: &mut EventLoop) -> Result<()> {
+        if let Some(session) = self.session.take() {
+            try!(ev.remove(session.src));
+        }
+        if let Some(retry) = self.retry.take() {
+            try!(ev.remove(retry));
+        }
+        Ok(())
+    }
+
+    fn action(&self, cmd: &str) -> String {
+        format!("mpd:{}:{}", self.name, cmd)
+    }
+
+    fn command(&self, action: &str) -> Option<&'static str> {
+        ["pause", "previous", "next"].iter().cloned().find(|&cmd| action == self.action(cmd))
+    }
+
+    fn connect(&mut self, ev: &mut EventLoop) -> Result<()> {
+        let conn = match Conn::connect(&self.addr) {
+            Ok(conn) => conn,
+            Err(err) => {
+                let err = Error::new(format!("failed to connect to MPD at `{}`: {}",
+                                             self.addr, err));
+                return self.disconnect(ev, err);
+            },
+        };
+        let src = try!(ev.add_fd(conn.as_raw_fd()));
+
+        let mut pending = VecDeque::new();
+        pending.push_back(Request::Greeting);
+        let mut session = Session {
+            conn: conn,
+            src: src,
+            buf: Vec::new(),
+            pending: pending,
+            fields: Vec::new(),
+            queued: Vec::new(),
+            cancelled: false,
+        };
+        match refresh(&mut session) {
+            Ok(()) => {
+                self.session = Some(session);
+                self.error = None;
+                Ok(())
+            },
+            Err(err) => {
+                self.session = Some(session);
+                self.disconnect(ev, err)
+            },
+        }
+    }
+
+    /// Drops the connection after `err`, and tries again later.
+    fn disconnect(&mut self, ev: &mut EventLoop, err: Error) -> Result<()> {
+        if let Some(session) = self.session.take() {
+            try!(ev.remove(session.src));
+        }
+        let msg = err.to_string();
+        if self.error.as_ref() != Some(&msg) {
+            ::log_error(&msg);
+        }
+        self.error = Some(msg);
+        self.state = State::Stop;
+        if self.retry.is_none() {
+            self.retry = Some(try!(ev.add_oneshot(Duration::from_secs(RECONNECT))));
+        }
+        Ok(())
+    }
+
+    /// Reads whatever MPD sent, returning `true` if what is shown changed.
+    fn receive(&mut self) -> Result<bool> {
+        let mut lines = Vec::new();
+        {
+            let session = match self.session {
+                Some(ref mut session) => session,
+                None => return Ok(false),
+            };
+            let mut chunk = [0u8; 4096];
+            loop {
+                match session.conn.read(&mut chunk) {
+                    Ok(0) => return Err(Error::new("MPD closed the connection")),
+                    Ok(n) => session.buf.extend_from_slice(&chunk[..n]),
+                    Err(ref err) if err.kind() == ErrorKind::WouldBlock => break,
+                    Err(ref err) if err.kind() == ErrorKind::Interrupted => {},
+                    Err(err) => return Err(Error::from(err)),
+                }
+            }
+            while let Some(end) = session.buf.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = session.buf.drain(..end + 1).collect();
+                lines.push(String::from_utf8_lossy(&line[..end]).into_owned());
+            }
+        }
+
+        let mut changed = false;
+        for line in lines {
+            changed |= try!(self.line(&line));
+        }
+        Ok(changed)
+    }
+
+    /// Handles a line of a response, returning `true` if what is shown changed.
+    fn line(&mut self, line: &str) -> Result<bool> {
+        let (req, fields) = {
+            let session = match self.session {
+                Some(ref mut session) => session,
+                None => return Ok(false),
+            };
+            let done = line == "OK" || line.starts_with("OK MPD ") || line.starts_with("ACK ");
+            if !done {
+                if let Some(mid) = line.find(": ") {
+                    session.fields.push((String::from(&line[..mid]),
+                                         String::from(&line[mid + 2..])));
+                }
+                return Ok(false);
+            }
+            let req = try!(session.pending.pop_front()
+                           .ok_or(Error::new("unexpected response from MPD")));
+            if line.starts_with("ACK") && req != Request::Command {
+                return Err(Error::new(format!("MPD refused a request: {}", line)));
+            }
+            let fields = mem::replace(&mut session.fields, Vec::new());
+            if req == Request::Idle {
+                try!(resume(session));
+            }
+            (req, fields)
+        };
+
+        match req {
+            Request::Greeting | Request::Command | Request::Idle => Ok(false),
+            Request::Status => {
+                self.status(&fields);
+                Ok(true)
+            },
+            Request::CurrentSong => {
+                self.song(&fields);
+                Ok(true)
+            },
+        }
+    }
+
+    fn status(&mut self, fields: &[(String, String)]) {
+        self.state = State::Stop;
+        self.duration = None;
+        self.volume = None;
+        let mut elapsed = 0.0;
+        for &(ref key, ref val) in fields {
+            match &**key {
+                "state" => {
+                    self.state = match &**val {
+                        "play" => State::Play,
+                        "pause" => State::Pause,
+                        _ => State::Stop,
+                    };
+                },
+                "elapsed" => elapsed = val.parse().unwrap_or(0.0),
+                "duration" => self.duration = val.parse().ok(),
+                "volume" => self.volume = val.parse::<i64>().ok().and_then(|v| {
+                    // MPD reports -1 when there is no mixer.
+                    if v < 0 { None } else { Some(v) }
+                }),
+                _ => {},
+            }
+        }
+        self.elapsed = (elapsed, Instant::now());
+    }
+
+    fn song(&mut self, fields: &[(String, String)]) {
+        self.artist.clear();
+        self.title.clear();
+        let mut file = String::new();
+        for &(ref key, ref val) in fields {
+            match &**key {
+                "Artist" => self.artist = val.clone(),
+                "Title" => self.title = val.clone(),
+                "file" => file = val.clone(),
+                _ => {},
+            }
+        }
+        // Untagged files are shown by name.
+        if self.title.is_empty() {
+            self.title = file.rsplit('/').next().unwrap_or("").to_string();
+        }
+    }
+
+    /// Seconds played so far.
+    fn elapsed(&self) -> f64 {
+        let (elapsed, at) = self.elapsed;
+        if self.state == State::Play {
+            let since = at.elapsed();
+            elapsed + since.as_secs() as f64 + since.subsec_nanos() as f64 / 1e9
+        } else {
+            elapsed
+        }
+    }
+}
+
+impl Format for Mpd {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<()> {
+        if self.session.is_none() || self.state == State::Stop {
+            return Ok(());
+        }
+
+        let mut song = if self.artist.is_empty() {
+            self.title.clone()
+        } else {
+            format!("{} - {}", self.artist, self.title)
+        };
+        if let Some(max) = self.max_width {
+            song = text::truncate(&song, max);
+        }
+        let time = match self.duration {
+            Some(duration) => format!("{}/{}", minutes(self.elapsed()), minutes(duration)),
+            None => minutes(self.elapsed()),
+        };
+        let icon = if self.state == State::Play { PLAYING } else { PAUSED };
+
+        // Colons end the action, so any in the name have to be escaped.
+        let escape = |cmd: &str| self.action(cmd).replace(":", "\\:");
+        try!(fmt.write(&*format!("%{{A1:{}:}}%{{A2:{}:}}%{{A3:{}:}}",
+                                 escape("pause"), escape("previous"), escape("next"))));
+        try!(fmt.write(&*format!("{} {} {}", icon, text::escape(&song), time)));
+        fmt.write("%{A}%{A}%{A}")
+    }
+}
+
+impl ToJson for Mpd {
+    fn to_json(&self) -> Json {
+        let state = match self.state {
+            State::Play => "play",
+            State::Pause => "pause",
+            State::Stop => "stop",
+        };
+        let mut obj = BTreeMap::new();
+        obj.insert(String::from("connected"), self.session.is_some().to_json());
+        obj.insert(String::from("state"), state.to_json());
+        obj.insert(String::from("artist"), self.artist.to_json());
+        obj.insert(String::from("title"), self.title.to_json());
+        obj.insert(String::from("elapsed"), self.elapsed().to_json());
+        obj.insert(String::from("duration"), self.duration.to_json());
+        obj.insert(String::from("volume"), self.volume.to_json());
+        Json::Object(obj)
+    }
+}
+
+/// Asks for the status and current song, then waits for them to change.
+fn refresh(session: &mut Session) -> Result<()> {
+    try!(session.conn.write_all(b"status\ncurrentsong\nidle player mixer\n"));
+    session.pending.push_back(Request::Status);
+    session.pending.push_back(Request::CurrentSong);
+    session.pending.push_back(Request::Idle);
+    Ok(())
+}
+
+/// Sends the commands queued while idling, once the `idle` has ended, then starts over.
+fn resume(session: &mut Session) -> Result<()> {
+    let cmds: Vec<&'static str> = session.queued.drain(..).collect();
+    for cmd in cmds {
+        try!(session.conn.write_all(cmd.as_bytes()));
+        try!(session.conn.write_all(b"\n"));
+        session.pending.push_back(Request::Command);
+    }
+    session.cancelled = false;
+    refresh(session)
+}
+
+/// Connects to `addr` like `TcpStream::connect`, but gives up on each address it resolves to
+/// after `timeout` milliseconds.
+fn connect_tcp(addr: &str, timeout: libc::c_int) -> io::Result<TcpStream> {
+    let mut last = io::Error::new(ErrorKind::InvalidInput, "address resolved to nothing");
+    for addr in try!(addr.to_socket_addrs()) {
+        match connect_addr(&addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last = err,
+        }
+    }
+    Err(last)
+}
+
+fn connect_addr(addr: &SocketAddr, timeout: libc::c_int) -> io::Result<TcpStream> {
+    let (storage, len) = sockaddr(addr);
+    let family = storage.ss_family as libc::c_int;
+    let fd = unsafe {
+        libc::socket(family, libc::SOCK_STREAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC, 0)
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Owned from here on, so it is closed on error.
+    let stream = unsafe { TcpStream::from_raw_fd(fd) };
+
+    let ret = unsafe {
+        libc::connect(fd, &storage as *const _ as *const libc::sockaddr, len)
+    };
+    if ret == 0 {
+        return Ok(stream);
+    }
+    let err = io::Error::last_os_error();
+    if err.raw_os_error() != Some(libc::EINPROGRESS) {
+        return Err(err);
+    }
+
+    let mut pollfd = libc::pollfd {
+        fd: fd,
+        events: libc::POLLOUT,
+        revents: 0,
+    };
+    match unsafe { libc::poll(&mut pollfd, 1, timeout) } {
+        -1 => return Err(io::Error::last_os_error()),
+        0 => return Err(io::Error::new(ErrorKind::TimedOut, "connection timed out")),
+        _ => {},
+    }
+    let mut error: libc::c_int = 0;
+    let mut size = mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(fd, libc::SOL_SOCKET, libc::SO_ERROR,
+                         &mut error as *mut _ as *mut libc::c_void, &mut size)
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if error != 0 {
+        return Err(io::Error::from_raw_os_error(error));
+    }
+    Ok(stream)
+}
+
+/// Converts `addr` to what `connect` takes.
+fn sockaddr(addr: &SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+    let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let len = match *addr {
+        SocketAddr::V4(ref addr) => {
+            let sin = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in) };
+            sin.sin_family = libc::AF_INET as libc::sa_family_t;
+            sin.sin_port = addr.port().to_be();
+            // Octets are already in network order.
+            sin.sin_addr.s_addr = unsafe { mem::transmute(addr.ip().octets()) };
+            mem::size_of::<libc::sockaddr_in>()
+        },
+        SocketAddr::V6(ref addr) => {
+            let sin6 = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in6) };
+            sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            sin6.sin6_port = addr.port().to_be();
+            sin6.sin6_flowinfo = addr.flowinfo();
+            sin6.sin6_addr.s6_addr = addr.ip().octets();
+            sin6.sin6_scope_id = addr.scope_id();
+            mem::size_of::<libc::sockaddr_in6>()
+        },
+    };
+    (storage, len as libc::socklen_t)
+}
+
+/// Where MPD listens according to `MPD_HOST` and `MPD_PORT`, as `mpc` has it.
+fn default_addr() -> String {
+    let host = env::var("MPD_HOST").unwrap_or(String::from("localhost"));
+    if host.starts_with('/') {
+        return host;
+    }
+    let port = env::var("MPD_PORT").unwrap_or(String::from("6600"));
+    format!("{}:{}", host, port)
+}
+
+fn minutes(secs: f64) -> String {
+    let secs = secs as u64;
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::prelude::*;
+    use std::io::BufReader;
+    use std::net::TcpListener;
+    use std::os::unix::net::{
+        UnixListener,
+        UnixStream,
+    };
+    use std::time::Duration;
+
+    use rustc_serialize::json::ToJson;
+
+    use event::{
+        Event,
+        EventLoop,
+    };
+    use util;
+    use util::testing::TempPath;
+    use super::{
+        connect_tcp,
+        Mpd,
+        State,
+    };
+
+    /// A fake MPD listening on a Unix socket.
+    struct Server {
+        path: TempPath,
+        listener: UnixListener,
+    }
+
+    impl Server {
+        fn new(name: &str) -> Server {
+            let path = TempPath::new(&format!("mpd-{}", name));
+            Server {
+                listener: UnixListener::bind(path.path()).unwrap(),
+                path: path,
+            }
+        }
+
+        fn accept(&self) -> (UnixStream, BufReader<UnixStream>) {
+            let (stream, _) = self.listener.accept().unwrap();
+            stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            let reader = BufReader::new(stream.try_clone().unwrap());
+            (stream, reader)
+        }
+    }
+
+    fn mpd(server: &Server, ev: &mut EventLoop) -> Mpd {
+        let conf = util::Mpd {
+            name: String::from("music"),
+            align: String::from("center"),
+            index: 0,
+            address: Some(server.path.path().to_string_lossy().into_owned()),
+            max_width: None,
+        };
+        Mpd::new(&conf, ev).unwrap()
+    }
+
+    /// Reads the next request the module sent.
+    fn request(reader: &mut BufReader<UnixStream>) -> String {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        String::from(line.trim_right())
+    }
+
+    /// Feeds the module events until `done` holds, failing after a few seconds.
+    fn until<F>(mpd: &mut Mpd, ev: &mut EventLoop, done: F)
+        where F: Fn(&Mpd) -> bool
+    {
+        let deadline = ev.add_oneshot(Duration::from_secs(5)).unwrap();
+        while !done(mpd) {
+            let event = ev.next().unwrap();
+            assert!(event != Event::Timer(deadline), "MPD module never got there");
+            if mpd.owns(&event) {
+                mpd.handle(ev, event).unwrap();
+            }
+        }
+        ev.remove(deadline).unwrap();
+    }
+
+    #[test]
+    fn protocol() {
+        let server = Server::new("protocol");
+        let mut ev = EventLoop::new().unwrap();
+        let mut mpd = mpd(&server, &mut ev);
+        let (mut stream, mut reader) = server.accept();
+
+        assert_eq!(request(&mut reader), "status");
+        assert_eq!(request(&mut reader), "currentsong");
+        assert_eq!(request(&mut reader), "idle player mixer");
+        stream.write_all(b"OK MPD 0.19.0\n\
+                           volume: 80\nstate: play\nelapsed: 61.5\nduration: 200.1\nOK\n\
+                           file: music/song.ogg\nArtist: Someone\nTitle: Something\nOK\n")
+            .unwrap();
+        until(&mut mpd, &mut ev, |m| !m.title.is_empty());
+        assert_eq!(mpd.state, State::Play);
+        assert_eq!((&*mpd.artist, &*mpd.title), ("Someone", "Something"));
+        assert_eq!((mpd.duration, mpd.volume), (Some(200.1), Some(80)));
+
+        // Clicks cancel the `idle` once, and are sent when it ends.
+        mpd.click(&mut ev, "mpd:music:next").unwrap();
+        mpd.click(&mut ev, "mpd:music:pause").unwrap();
+        assert_eq!(request(&mut reader), "noidle");
+        stream.write_all(b"changed: player\nOK\n").unwrap();
+        until(&mut mpd, &mut ev, |m| m.session.as_ref().map_or(true, |s| !s.cancelled));
+        assert_eq!(request(&mut reader), "next");
+        assert_eq!(request(&mut reader), "pause");
+        assert_eq!(request(&mut reader), "status");
+        assert_eq!(request(&mut reader), "currentsong");
+        assert_eq!(request(&mut reader), "idle player mixer");
+
+        // A refused click doesn't drop the connection.
+        stream.write_all(b"ACK [2@0] {next} no next song\nOK\n\
+                           volume: -1\nstate: pause\nelapsed: 61.5\nOK\n\
+                           file: music/untagged.ogg\nOK\n")
+            .unwrap();
+        until(&mut mpd, &mut ev, |m| m.state == State::Pause && m.title == "untagged.ogg");
+        assert!(mpd.session.is_some());
+        assert!(mpd.error.is_none());
+        assert_eq!(mpd.volume, None);
+        assert_eq!(mpd.artist, "");
+
+        // Anything else refused does.
+        stream.write_all(b"ACK [5@0] {idle} no\n").unwrap();
+        until(&mut mpd, &mut ev, |m| m.session.is_none());
+        assert!(mpd.error.as_ref().map_or(false, |e| e.contains("{idle} no")));
+        assert!(mpd.retry.is_some());
+        assert_eq!(mpd.to_json().find("connected").and_then(|c| c.as_boolean()), Some(false));
+        mpd.stop(&mut ev).unwrap();
+    }
+
+    #[test]
+    fn closed() {
+        let server = Server::new("closed");
+        let mut ev = EventLoop::new().unwrap();
+        let mut mpd = mpd(&server, &mut ev);
+        let (stream, mut reader) = server.accept();
+        assert_eq!(request(&mut reader), "status");
+        assert_eq!(request(&mut reader), "currentsong");
+        assert_eq!(request(&mut reader), "idle player mixer");
+        drop(stream);
+        drop(reader);
+        until(&mut mpd, &mut ev, |m| m.session.is_none());
+        assert_eq!(mpd.error.as_ref().map(|e| &**e), Some("MPD closed the connection"));
+        mpd.stop(&mut ev).unwrap();
+    }
+
+    #[test]
+    fn tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        assert!(connect_tcp(&addr, 500).is_ok());
+        drop(listener);
+        assert!(connect_tcp(&addr, 500).is_err());
+        assert!(connect_tcp("not an address", 500).is_err());
+    }
+}
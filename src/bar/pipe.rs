@@ -9,11 +9,19 @@ use std::io::{
     Read,
     Result,
     Error,
+    ErrorKind,
 };
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::FileTypeExt;
 use std::process::Stdio;
+use std::path::Path;
+use std::ffi::CString;
+use std::fs;
 use std::mem;
 use libc;
 
+use sys;
+
 #[cfg(any(target_os = "macos",
           target_os = "ios",
           target_os = "freebsd",
@@ -59,6 +67,49 @@ pub fn pipe() -> Result<(PipeReader, PipeWriter)> {
     }
 }
 
+/// Opens the named FIFO at `path`, creating it if it doesn't exist. Also returns whether it was
+/// created.
+///
+/// Both ends are opened, so the reader never sees the end of file when outside writers come and
+/// go.
+pub fn fifo(path: &Path) -> Result<(PipeReader, PipeWriter, bool)> {
+    let cpath = try!(CString::new(path.as_os_str().as_bytes())
+                     .map_err(|_| Error::new(ErrorKind::InvalidInput, "path contains a nul byte")));
+    unsafe {
+        let created = libc::mkfifo(cpath.as_ptr(), 0o600) == 0;
+        if !created {
+            let err = Error::last_os_error();
+            if err.raw_os_error() != Some(libc::EEXIST) {
+                return Err(err);
+            }
+            if !try!(fs::metadata(path)).file_type().is_fifo() {
+                return Err(Error::new(ErrorKind::InvalidInput, "not a FIFO"));
+            }
+        }
+
+        // With a reader already open, opening the write end can't block.
+        let rd = libc::open(cpath.as_ptr(), libc::O_RDONLY | libc::O_NONBLOCK | libc::O_CLOEXEC);
+        let wr = if rd == -1 {
+            -1
+        } else {
+            libc::open(cpath.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC)
+        };
+        if wr == -1 {
+            let err = Error::last_os_error();
+            if rd != -1 {
+                libc::close(rd);
+            }
+            // Don't leave behind a FIFO nobody is reading.
+            if created {
+                libc::unlink(cpath.as_ptr());
+            }
+            return Err(err);
+        }
+        let rd = PipeReader::from_raw_fd(rd);
+        Ok((rd, PipeWriter::from_raw_fd(wr), created))
+    }
+}
+
 impl PipeWriter {
     /// Duplicates the underlying file descriptor, returning a new handle to it.
     pub fn dup(&self) -> Result<PipeWriter> {
@@ -151,6 +202,143 @@ impl Write for PipeWriter {
     }
 }
 
+/// A thin wrapper around an `epoll` instance.
+#[derive(Debug)]
+pub struct Poller(FileDesc);
+
+impl Poller {
+    pub fn new() -> Result<Poller> {
+        let fd = unsafe { sys::epoll_create1(sys::EPOLL_CLOEXEC) };
+        if fd == -1 {
+            Err(Error::last_os_error())
+        } else {
+            unsafe {
+                Ok(Poller(FileDesc::from_raw_fd(fd)))
+            }
+        }
+    }
+
+    /// Watches `fd` for readability, reporting it as `token`.
+    pub fn add(&self, fd: RawFd, token: u64) -> Result<()> {
+        let mut event = sys::epoll_event {
+            events: sys::EPOLLIN,
+            data: token,
+        };
+        let ret = unsafe {
+            sys::epoll_ctl(self.0.as_raw_fd(), sys::EPOLL_CTL_ADD, fd, &mut event)
+        };
+        if ret == -1 {
+            Err(Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn remove(&self, fd: RawFd) -> Result<()> {
+        // Kernels before 2.6.9 require a non-null event, even though it is ignored.
+        let mut event = sys::epoll_event {
+            events: 0,
+            data: 0,
+        };
+        let ret = unsafe {
+            sys::epoll_ctl(self.0.as_raw_fd(), sys::EPOLL_CTL_DEL, fd, &mut event)
+        };
+        if ret == -1 {
+            Err(Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Waits up to `timeout` milliseconds (or forever, if negative) for any watched file
+    /// descriptor to become ready, returning the tokens of those that are.
+    ///
+    /// An interrupted wait returns no tokens.
+    pub fn wait(&self, timeout: i32) -> Result<Vec<u64>> {
+        let mut events = [sys::epoll_event { events: 0, data: 0 }; 32];
+        let ret = unsafe {
+            sys::epoll_wait(self.0.as_raw_fd(),
+                       events.as_mut_ptr(),
+                       events.len() as libc::c_int,
+                       timeout as libc::c_int)
+        };
+        if ret == -1 {
+            let err = Error::last_os_error();
+            if err.kind() == ErrorKind::Interrupted {
+                Ok(Vec::new())
+            } else {
+                Err(err)
+            }
+        } else {
+            Ok(events[..ret as usize].iter().map(|e| e.data).collect())
+        }
+    }
+}
+
+impl AsRawFd for Poller {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+/// Buffers non-blocking reads from a pipe, splitting them into lines.
+#[derive(Debug)]
+pub struct LineReader {
+    reader: PipeReader,
+    buf: Vec<u8>,
+}
+
+impl LineReader {
+    pub fn new(reader: PipeReader) -> Result<LineReader> {
+        try!(reader.0.set_nonblocking());
+        Ok(LineReader {
+            reader: reader,
+            buf: Vec::new(),
+        })
+    }
+
+    /// Reads everything currently available, returning `true` if the pipe has been closed.
+    pub fn fill(&mut self) -> Result<bool> {
+        let mut chunk = [0u8; 4096];
+        loop {
+            match self.reader.read(&mut chunk) {
+                Ok(0) => return Ok(true),
+                Ok(n) => self.buf.extend_from_slice(&chunk[..n]),
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => return Ok(false),
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => {},
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Removes and returns the next complete line, without its terminator.
+    pub fn next_line(&mut self) -> Option<String> {
+        let end = match self.buf.iter().position(|&b| b == b'\n') {
+            Some(end) => end,
+            None => return None,
+        };
+        let rest = self.buf.split_off(end + 1);
+        let line = mem::replace(&mut self.buf, rest);
+        Some(String::from_utf8_lossy(&line[..end]).into_owned())
+    }
+
+    /// Removes and returns any trailing data not terminated by a newline.
+    pub fn take_partial(&mut self) -> Option<String> {
+        if self.buf.is_empty() {
+            None
+        } else {
+            let line = mem::replace(&mut self.buf, Vec::new());
+            Some(String::from_utf8_lossy(&line).into_owned())
+        }
+    }
+}
+
+impl AsRawFd for LineReader {
+    fn as_raw_fd(&self) -> RawFd {
+        self.reader.as_raw_fd()
+    }
+}
+
 #[derive(Debug)]
 pub struct FileDesc {
     fd: RawFd,
@@ -203,6 +391,17 @@ impl FileDesc {
         }
     }
 
+    pub fn set_nonblocking(&self) -> Result<()> {
+        unsafe {
+            let flags = libc::fcntl(self.fd, libc::F_GETFL);
+            if flags == -1 || libc::fcntl(self.fd, libc::F_SETFL, flags | libc::O_NONBLOCK) == -1 {
+                Err(Error::last_os_error())
+            } else {
+                Ok(())
+            }
+        }
+    }
+
     pub fn set_cloexec(&self) {
         extern {
             fn ioctl(fd: libc::c_int, req: libc::c_ulong, ...) -> libc::c_int;
@@ -0,0 +1,280 @@
+//! Modules loaded from shared libraries.
+//!
+//! A plugin is a shared library exporting two symbols:
+//!
+//! ```c
+//! const uint32_t bar_plugin_abi_version = 1;
+//! int bar_plugin_register(const struct bar_registrar *registrar);
+//! ```
+//!
+//! `bar_plugin_abi_version` is checked before anything else in the library is touched, and
+//! plugins built for a different version are refused. `bar_plugin_register` then calls
+//! `registrar->register` once for every module it provides, passing a `bar_module` (see
+//! `ModuleVTable`), and returns zero on success.
+//!
+//! All callbacks are made from the main loop, so they must not block.
+
+use std::ffi::{
+    CStr,
+    CString,
+};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::rc::Rc;
+use std::fs;
+use std::mem;
+use std::ptr;
+use std::result;
+use libc::{
+    self,
+    c_char,
+    c_int,
+    c_void,
+    size_t,
+};
+
+use bar::{
+    Format,
+    Formatter,
+};
+use data::Provider;
+use sys;
+use util::{
+    Result,
+    Error,
+    ParseError,
+    ParseErrorKind,
+};
+
+/// Version of the plugin ABI this build implements.
+pub const ABI_VERSION: u32 = 1;
+
+const VERSION_SYMBOL: &'static [u8] = b"bar_plugin_abi_version\0";
+const ENTRY_SYMBOL: &'static [u8] = b"bar_plugin_register\0";
+
+/// Initial size of the buffer modules format themselves into.
+const FORMAT_BUF: usize = 256;
+
+/// Passed to a plugin's entrypoint.
+#[repr(C)]
+pub struct Registrar {
+    pub abi_version: u32,
+    /// Must be passed back to `register` as is.
+    pub host: *mut c_void,
+    /// Registers a module, returning zero on success. The module is copied, so it need not
+    /// outlive the call.
+    pub register: extern "C" fn(host: *mut c_void, module: *const ModuleVTable) -> c_int,
+}
+
+/// A module as described by a plugin. `state` is passed to every callback.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct ModuleVTable {
+    /// Must be `ABI_VERSION`.
+    pub abi_version: u32,
+    /// NUL-terminated name of the module. Only read during registration.
+    pub name: *const c_char,
+    pub state: *mut c_void,
+    /// Writes the module's text, formatting tags included, into `buf`, returning its length. If
+    /// that exceeds `len` nothing need be written, and the call is repeated with a bigger buffer.
+    pub format: extern "C" fn(state: *mut c_void, buf: *mut c_char, len: size_t) -> size_t,
+    /// Consumes a line of output from the module's command, returning zero if it was understood.
+    pub consume: Option<extern "C" fn(state: *mut c_void,
+                                      line: *const c_char,
+                                      len: size_t) -> c_int>,
+    /// Called every second, returning non-zero if the text changed.
+    pub tick: Option<extern "C" fn(state: *mut c_void) -> c_int>,
+    /// Frees `state`. Called once the module is no longer used.
+    pub free: Option<extern "C" fn(state: *mut c_void)>,
+}
+
+type EntryFn = extern "C" fn(registrar: *const Registrar) -> c_int;
+
+/// An open shared library.
+struct Library {
+    handle: *mut c_void,
+}
+
+impl Library {
+    fn open(path: &Path) -> Result<Library> {
+        let cpath = try!(CString::new(path.as_os_str().as_bytes())
+                         .map_err(|_| Error::new("path contains a nul byte")));
+        let handle = unsafe { libc::dlopen(cpath.as_ptr(), sys::RTLD_NOW | sys::RTLD_LOCAL) };
+        if handle.is_null() {
+            Err(Error::new(dlerror()))
+        } else {
+            Ok(Library {
+                handle: handle,
+            })
+        }
+    }
+
+    /// Looks up `name`, which must be NUL-terminated.
+    fn symbol(&self, name: &[u8]) -> Option<*mut c_void> {
+        let sym = unsafe { libc::dlsym(self.handle, name.as_ptr() as *const c_char) };
+        if sym.is_null() {
+            None
+        } else {
+            Some(sym)
+        }
+    }
+}
+
+impl Drop for Library {
+    fn drop(&mut self) {
+        unsafe {
+            libc::dlclose(self.handle);
+        }
+    }
+}
+
+fn dlerror() -> String {
+    unsafe {
+        let err = libc::dlerror();
+        if err.is_null() {
+            String::from("unknown dynamic linker error")
+        } else {
+            CStr::from_ptr(err).to_string_lossy().into_owned()
+        }
+    }
+}
+
+/// A module provided by a plugin.
+pub struct Module {
+    name: String,
+    vtable: ModuleVTable,
+    // Dropped after the module, so its code is still around to free `state`.
+    _lib: Rc<Library>,
+}
+
+impl Module {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Gives the module a chance to update itself, returning `true` if its text changed.
+    pub fn tick(&mut self) -> bool {
+        match self.vtable.tick {
+            Some(tick) => tick(self.vtable.state) != 0,
+            None => false,
+        }
+    }
+}
+
+impl Format for Module {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<()> {
+        let mut buf = vec![0u8; FORMAT_BUF];
+        loop {
+            let len = (self.vtable.format)(self.vtable.state,
+                                           buf.as_mut_ptr() as *mut c_char,
+                                           buf.len() as size_t) as usize;
+            if len <= buf.len() {
+                return fmt.write(&*String::from_utf8_lossy(&buf[..len]));
+            }
+            buf = vec![0u8; len];
+        }
+    }
+}
+
+impl Provider for Module {
+    fn consume(&mut self, data: &str) -> result::Result<(), ParseError> {
+        let consume = match self.vtable.consume {
+            Some(consume) => consume,
+            None => return Ok(()),
+        };
+        let ret = consume(self.vtable.state, data.as_ptr() as *const c_char, data.len() as size_t);
+        if ret == 0 {
+            Ok(())
+        } else {
+            Err(ParseError::with_field(data, &self.name, ParseErrorKind::Rejected))
+        }
+    }
+}
+
+impl Drop for Module {
+    fn drop(&mut self) {
+        if let Some(free) = self.vtable.free {
+            free(self.vtable.state);
+        }
+    }
+}
+
+/// Loads the plugin at `path`, returning the modules it provides.
+pub fn load(path: &Path) -> Result<Vec<Module>> {
+    let lib = try!(Library::open(path));
+
+    let version = match lib.symbol(VERSION_SYMBOL) {
+        Some(sym) => unsafe { *(sym as *const u32) },
+        None => return Err(Error::new("not a plugin: `bar_plugin_abi_version` is missing")),
+    };
+    if version != ABI_VERSION {
+        return Err(Error::new(format!("built for plugin ABI version {}, but version {} is \
+                                       required", version, ABI_VERSION)));
+    }
+    let entry = match lib.symbol(ENTRY_SYMBOL) {
+        Some(sym) => unsafe { mem::transmute::<*mut c_void, EntryFn>(sym) },
+        None => return Err(Error::new("not a plugin: `bar_plugin_register` is missing")),
+    };
+
+    let mut registered: Registered = Vec::new();
+    let registrar = Registrar {
+        abi_version: ABI_VERSION,
+        host: &mut registered as *mut Registered as *mut c_void,
+        register: register,
+    };
+    let ret = entry(&registrar);
+
+    let lib = Rc::new(lib);
+    let modules: Vec<Module> = registered.into_iter().map(|(name, vtable)| {
+        Module {
+            name: name,
+            vtable: vtable,
+            _lib: lib.clone(),
+        }
+    }).collect();
+
+    // The modules that did register are dropped here, freeing their state.
+    if ret != 0 {
+        return Err(Error::new(format!("failed to register (status {})", ret)));
+    }
+    Ok(modules)
+}
+
+/// Loads every plugin in `dir`, i.e. every file ending in `.so`.
+///
+/// Plugins that fail to load are reported and skipped.
+pub fn load_dir(dir: &Path) -> Result<Vec<Module>> {
+    let mut modules = Vec::new();
+    for entry in try!(fs::read_dir(dir)) {
+        let path = try!(entry).path();
+        if path.extension().map_or(true, |ext| ext != "so") {
+            continue;
+        }
+        match load(&path) {
+            Ok(loaded) => modules.extend(loaded),
+            Err(err) => {
+                ::log_error(&format!("failed to load plugin `{}`: {}", path.display(), err));
+            },
+        }
+    }
+    Ok(modules)
+}
+
+/// Modules registered so far, with their names.
+type Registered = Vec<(String, ModuleVTable)>;
+
+extern "C" fn register(host: *mut c_void, module: *const ModuleVTable) -> c_int {
+    if host.is_null() || module.is_null() {
+        return -1;
+    }
+    let (registered, module) = unsafe {
+        (&mut *(host as *mut Registered), &*module)
+    };
+    // Refuse modules built against another layout before reading anything else.
+    if module.abi_version != ABI_VERSION || module.name.is_null() {
+        return -1;
+    }
+    let name = unsafe { CStr::from_ptr(module.name).to_string_lossy().into_owned() };
+    registered.push((name, ModuleVTable { name: ptr::null(), .. *module }));
+    0
+}
@@ -0,0 +1,175 @@
+use std::process::{
+    Command,
+    Stdio,
+};
+use std::collections::BTreeMap;
+use std::result;
+
+use rustc_serialize::json::{
+    Json,
+    ToJson,
+};
+
+use bar::{
+    Align,
+    Color,
+    Format,
+    Formatter,
+    Position,
+};
+use data::Provider;
+use util::{
+    self,
+    text,
+    Result,
+    ParseError,
+    ParseErrorKind,
+};
+
+/// A module rendered by an external command.
+///
+/// The command prints one JSON object per line, each replacing the last:
+///
+/// ```text
+/// {"text": "vol: 40%", "color": "#859900", "urgent": false, "on_click": "pavucontrol"}
+/// ```
+///
+/// Only `text` is required.
+#[derive(Debug)]
+pub struct Script {
+    name: String,
+    cmd: String,
+    pos: Position,
+    text: String,
+    color: Option<String>,
+    urgent: bool,
+    on_click: Option<String>,
+    errored: bool,
+}
+
+impl Script {
+    pub fn new(conf: &util::Script) -> Result<Script> {
+        let align: Align = try!(conf.align.parse());
+
+        Ok(Script {
+            name: conf.name.clone(),
+            cmd: conf.command.clone(),
+            pos: Position::new(align, conf.index),
+            text: String::new(),
+            color: None,
+            urgent: false,
+            on_click: None,
+            errored: false,
+        })
+    }
+
+    /// Returns the command whose output this provider consumes.
+    pub fn command(&self) -> Command {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c")
+           .arg(&self.cmd)
+           .stdin(Stdio::null())
+           .stderr(Stdio::inherit());
+        cmd
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn position(&self) -> Position {
+        self.pos
+    }
+
+    /// The command to run when the module is clicked.
+    pub fn on_click(&self) -> Option<&str> {
+        self.on_click.as_ref().map(|c| &**c)
+    }
+
+    /// Returns `true` if the last line couldn't be understood.
+    pub fn is_errored(&self) -> bool {
+        self.errored
+    }
+
+    /// The name `lemonbar` prints when the module is clicked.
+    pub fn action(&self) -> String {
+        format!("script:{}", self.name)
+    }
+
+    fn update(&mut self, data: &str) -> result::Result<(), ParseError> {
+        let json = try!(Json::from_str(data)
+                        .map_err(|_| ParseError::new(data, ParseErrorKind::InvalidJson)));
+        let obj = try!(json.as_object()
+                       .ok_or(ParseError::new(data, ParseErrorKind::InvalidJson)));
+
+        let text = try!(obj.get("text").and_then(|t| t.as_string())
+                        .ok_or(ParseError::with_field(data, "text",
+                                                      ParseErrorKind::MissingValue)));
+        let color = match obj.get("color").and_then(|c| c.as_string()) {
+            Some(color) if !util::is_color(color) => {
+                return Err(ParseError::with_field(data, "color", ParseErrorKind::UnknownValue));
+            },
+            color => color.map(String::from),
+        };
+
+        self.text = String::from(text);
+        self.color = color;
+        self.urgent = obj.get("urgent").and_then(|u| u.as_boolean()).unwrap_or(false);
+        self.on_click = obj.get("on_click").and_then(|c| c.as_string()).map(String::from);
+        Ok(())
+    }
+}
+
+impl Format for Script {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<()> {
+        if self.errored {
+            return fmt.write(&*format!("%{{F#{:x}}}{}: error%{{F-}}",
+                                       Color::Red as u32,
+                                       text::escape(&self.name)));
+        }
+
+        if self.on_click.is_some() {
+            // Colons end the action, so any in the name have to be escaped.
+            try!(fmt.write(&*format!("%{{A:{}:}}", self.action().replace(":", "\\:"))));
+        }
+        if self.urgent {
+            try!(fmt.write("%{R}"));
+        }
+        if let Some(ref color) = self.color {
+            try!(fmt.write(&*format!("%{{F{}}}", color)));
+        }
+
+        try!(fmt.write(&*text::escape(&self.text)));
+
+        if self.color.is_some() {
+            try!(fmt.write("%{F-}"));
+        }
+        if self.urgent {
+            try!(fmt.write("%{R}"));
+        }
+        if self.on_click.is_some() {
+            try!(fmt.write("%{A}"));
+        }
+        Ok(())
+    }
+}
+
+impl ToJson for Script {
+    fn to_json(&self) -> Json {
+        let mut obj = BTreeMap::new();
+        obj.insert(String::from("text"), self.text.to_json());
+        obj.insert(String::from("color"), self.color.to_json());
+        obj.insert(String::from("urgent"), self.urgent.to_json());
+        obj.insert(String::from("on_click"), self.on_click.to_json());
+        obj.insert(String::from("errored"), self.errored.to_json());
+        Json::Object(obj)
+    }
+}
+
+impl Provider for Script {
+    fn consume(&mut self, data: &str) -> result::Result<(), ParseError> {
+        let res = self.update(data);
+        self.errored = res.is_err();
+        res
+    }
+}
@@ -0,0 +1,301 @@
+use std::process::{
+    Child,
+    ChildStdin,
+    Command,
+};
+use std::os::unix::process::CommandExt;
+use std::time::{
+    Duration,
+    Instant,
+};
+use std::cmp;
+use std::fmt;
+use std::thread;
+use libc;
+
+use pipe::PipeWriter;
+use util::{
+    Result,
+    Error,
+};
+
+/// Delay before the first restart of a crashed child, in seconds. Doubles with every consecutive
+/// crash.
+const BACKOFF_BASE: u64 = 1;
+/// Upper bound on the restart delay, in seconds.
+const BACKOFF_MAX: u64 = 60;
+/// Children that stay up at least this many seconds have their backoff reset.
+const HEALTHY_AFTER: u64 = 30;
+/// How long children get to exit after `SIGTERM` before being killed, in milliseconds.
+const SHUTDOWN_GRACE: u64 = 1000;
+
+/// Identifies a child managed by a `Supervisor`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ChildId(usize);
+
+struct Managed {
+    name: String,
+    cmd: Command,
+    stdout: PipeWriter,
+    child: Option<Child>,
+    started: Instant,
+    failures: u32,
+}
+
+impl Managed {
+    fn start(&mut self) -> Result<()> {
+        let stdout = try!(self.stdout.stdio());
+        match self.cmd.stdout(stdout).spawn() {
+            Ok(child) => {
+                self.child = Some(child);
+                self.started = Instant::now();
+                Ok(())
+            },
+            Err(err) => {
+                self.failures += 1;
+                Err(Error::new(format!("failed to spawn `{}`: {}", self.name, err)))
+            },
+        }
+    }
+
+    fn backoff(&self) -> Duration {
+        let exp = cmp::min(self.failures.saturating_sub(1), 6);
+        Duration::from_secs(cmp::min(BACKOFF_BASE << exp, BACKOFF_MAX))
+    }
+
+    /// Reaps the child if it has exited, returning its raw status.
+    fn try_reap(&mut self) -> Option<libc::c_int> {
+        let pid = match self.child {
+            Some(ref child) => child.id() as libc::pid_t,
+            None => return None,
+        };
+        let mut status = 0;
+        if unsafe { libc::waitpid(pid, &mut status, libc::WNOHANG) } == pid {
+            self.child = None;
+            Some(status)
+        } else {
+            None
+        }
+    }
+}
+
+/// Keeps track of spawned children so they can be restarted when they die.
+pub struct Supervisor {
+    children: Vec<Option<Managed>>,
+    /// Fire-and-forget children, only tracked so they can be reaped.
+    detached: Vec<Child>,
+}
+
+impl Supervisor {
+    pub fn new() -> Supervisor {
+        Supervisor {
+            children: Vec::new(),
+            detached: Vec::new(),
+        }
+    }
+
+    /// Spawns `cmd` with its stdout connected to `stdout`.
+    ///
+    /// Only stdout is managed by the supervisor; stdin and stderr should be configured on `cmd`
+    /// beforehand.
+    pub fn spawn(&mut self, name: &str, mut cmd: Command, stdout: &PipeWriter) -> Result<ChildId> {
+        own_group(&mut cmd);
+        let mut managed = Managed {
+            name: String::from(name),
+            cmd: cmd,
+            stdout: try!(stdout.dup()),
+            child: None,
+            started: Instant::now(),
+            failures: 0,
+        };
+        try!(managed.start());
+
+        // Ids are never reused, so a restart still pending for a stopped child can't land on
+        // one spawned after it.
+        self.children.push(Some(managed));
+        Ok(ChildId(self.children.len() - 1))
+    }
+
+    /// Spawns `cmd` without supervising it.
+    ///
+    /// The child is neither restarted nor terminated on shutdown; it is only reaped once it exits.
+    pub fn run(&mut self, cmd: &mut Command) -> Result<()> {
+        let child = try!(cmd.spawn());
+        self.detached.push(child);
+        Ok(())
+    }
+
+    /// Takes the stdin handle of a running child, if it was spawned with a piped stdin.
+    pub fn take_stdin(&mut self, id: ChildId) -> Option<ChildStdin> {
+        self.get_mut(id).and_then(|m| m.child.as_mut()).and_then(|c| c.stdin.take())
+    }
+
+    pub fn name(&self, id: ChildId) -> Option<&str> {
+        self.get(id).map(|m| &*m.name)
+    }
+
+    pub fn is_running(&self, id: ChildId) -> bool {
+        self.get(id).map_or(false, |m| m.child.is_some())
+    }
+
+    /// Returns how long to wait before restarting the child.
+    pub fn backoff(&self, id: ChildId) -> Duration {
+        self.get(id).map_or(Duration::from_secs(BACKOFF_BASE), |m| m.backoff())
+    }
+
+    /// Collects every managed child that has exited.
+    ///
+    /// Only managed children are waited on, so this never steals the exit status of a process
+    /// spawned elsewhere.
+    pub fn reap(&mut self) -> Vec<Exit> {
+        let mut exits = Vec::new();
+
+        self.detached.retain(|child| {
+            let pid = child.id() as libc::pid_t;
+            let mut status = 0;
+            unsafe { libc::waitpid(pid, &mut status, libc::WNOHANG) != pid }
+        });
+
+        for (idx, slot) in self.children.iter_mut().enumerate() {
+            let managed = match *slot {
+                Some(ref mut managed) => managed,
+                None => continue,
+            };
+            let status = match managed.try_reap() {
+                Some(status) => status,
+                None => continue,
+            };
+
+            if managed.started.elapsed() >= Duration::from_secs(HEALTHY_AFTER) {
+                managed.failures = 0;
+            }
+            managed.failures += 1;
+
+            exits.push(Exit {
+                id: ChildId(idx),
+                name: managed.name.clone(),
+                status: status,
+                backoff: managed.backoff(),
+            });
+        }
+        exits
+    }
+
+    /// Terminates every running child.
+    ///
+    /// Children are sent `SIGTERM`, and killed outright if they are still around once the grace
+    /// period is up.
+    pub fn shutdown(&mut self) {
+        terminate(self.children.iter_mut().filter_map(|m| m.as_mut()).collect());
+    }
+
+    /// Terminates a child and forgets about it, so it is never restarted.
+    pub fn stop(&mut self, id: ChildId) {
+        if let Some(mut managed) = self.children.get_mut(id.0).and_then(|m| m.take()) {
+            terminate(vec![&mut managed]);
+        }
+    }
+
+    /// Terminates a child and starts it again with a new command.
+    pub fn respawn(&mut self, id: ChildId, mut cmd: Command) -> Result<()> {
+        own_group(&mut cmd);
+        let managed = match self.get_mut(id) {
+            Some(managed) => managed,
+            None => return Err(Error::new("no such child")),
+        };
+        terminate(vec![&mut *managed]);
+        managed.cmd = cmd;
+        managed.failures = 0;
+        managed.start()
+    }
+
+    /// Respawns a child that has exited.
+    pub fn restart(&mut self, id: ChildId) -> Result<()> {
+        if let Some(managed) = self.get_mut(id) {
+            if managed.child.is_none() {
+                return managed.start();
+            }
+        }
+        Ok(())
+    }
+
+    fn get(&self, id: ChildId) -> Option<&Managed> {
+        self.children.get(id.0).and_then(|m| m.as_ref())
+    }
+
+    fn get_mut(&mut self, id: ChildId) -> Option<&mut Managed> {
+        self.children.get_mut(id.0).and_then(|m| m.as_mut())
+    }
+}
+
+/// Makes `cmd` start in a process group of its own, so that whatever it runs in turn can be
+/// signalled along with it.
+fn own_group(cmd: &mut Command) {
+    cmd.before_exec(|| {
+        unsafe {
+            libc::setpgid(0, 0);
+        }
+        Ok(())
+    });
+}
+
+/// Sends `SIGTERM` to the group of each child, then kills whichever are left after the grace
+/// period.
+fn terminate(mut children: Vec<&mut Managed>) {
+    for managed in &children {
+        if let Some(ref child) = managed.child {
+            unsafe {
+                libc::kill(-(child.id() as libc::pid_t), libc::SIGTERM);
+            }
+        }
+    }
+
+    let deadline = Instant::now() + Duration::from_millis(SHUTDOWN_GRACE);
+    loop {
+        for managed in &mut children {
+            managed.try_reap();
+        }
+        if children.iter().all(|m| m.child.is_none()) || Instant::now() >= deadline {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    for managed in &mut children {
+        if let Some(mut child) = managed.child.take() {
+            unsafe {
+                libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+            }
+            let _ = child.wait();
+        }
+    }
+}
+
+impl Drop for Supervisor {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// A managed child that has exited.
+#[derive(Debug, Clone)]
+pub struct Exit {
+    pub id: ChildId,
+    pub name: String,
+    /// The raw status returned by `waitpid`.
+    pub status: libc::c_int,
+    /// How long to wait before restarting it.
+    pub backoff: Duration,
+}
+
+impl fmt::Display for Exit {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let sig = self.status & 0x7f;
+        if sig == 0 {
+            try!(write!(fmt, "`{}` exited with status {}", self.name, (self.status >> 8) & 0xff));
+        } else {
+            try!(write!(fmt, "`{}` was killed by signal {}", self.name, sig));
+        }
+        write!(fmt, ", restarting in {}s", self.backoff.as_secs())
+    }
+}
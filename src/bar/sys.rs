@@ -0,0 +1,101 @@
+//! Linux bindings missing from `libc`.
+
+#![allow(non_camel_case_types)]
+
+use libc;
+
+pub const EPOLL_CLOEXEC: libc::c_int = 0x80000;
+pub const EPOLL_CTL_ADD: libc::c_int = 1;
+pub const EPOLL_CTL_DEL: libc::c_int = 2;
+pub const EPOLLIN: u32 = 0x1;
+
+#[repr(C)]
+#[cfg_attr(target_arch = "x86_64", repr(packed))]
+#[derive(Debug, Clone, Copy)]
+pub struct epoll_event {
+    pub events: u32,
+    pub data: u64,
+}
+
+pub const TFD_CLOEXEC: libc::c_int = 0x80000;
+pub const TFD_NONBLOCK: libc::c_int = 0x800;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct itimerspec {
+    pub it_interval: libc::timespec,
+    pub it_value: libc::timespec,
+}
+
+pub const SFD_CLOEXEC: libc::c_int = 0x80000;
+pub const SFD_NONBLOCK: libc::c_int = 0x800;
+
+pub const SIG_BLOCK: libc::c_int = 0;
+pub const SIGUSR1: libc::c_int = 10;
+
+#[repr(C)]
+pub struct signalfd_siginfo {
+    pub ssi_signo: u32,
+    pub ssi_errno: i32,
+    pub ssi_code: i32,
+    pub ssi_pid: u32,
+    pub ssi_uid: u32,
+    pub ssi_fd: i32,
+    pub ssi_tid: u32,
+    pub ssi_band: u32,
+    pub ssi_overrun: u32,
+    pub ssi_trapno: u32,
+    pub ssi_status: i32,
+    _pad: [u8; 84],
+}
+
+pub const IN_CLOEXEC: libc::c_int = 0x80000;
+pub const IN_NONBLOCK: libc::c_int = 0x800;
+pub const IN_MODIFY: u32 = 0x2;
+pub const IN_CLOSE_WRITE: u32 = 0x8;
+pub const IN_MOVED_TO: u32 = 0x80;
+pub const IN_CREATE: u32 = 0x100;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct inotify_event {
+    pub wd: libc::c_int,
+    pub mask: u32,
+    pub cookie: u32,
+    pub len: u32,
+}
+
+pub const RTLD_NOW: libc::c_int = 0x2;
+pub const RTLD_LOCAL: libc::c_int = 0;
+
+extern {
+    pub fn epoll_create1(flags: libc::c_int) -> libc::c_int;
+    pub fn epoll_ctl(epfd: libc::c_int,
+                     op: libc::c_int,
+                     fd: libc::c_int,
+                     event: *mut epoll_event) -> libc::c_int;
+    pub fn epoll_wait(epfd: libc::c_int,
+                      events: *mut epoll_event,
+                      maxevents: libc::c_int,
+                      timeout: libc::c_int) -> libc::c_int;
+
+    pub fn timerfd_create(clockid: libc::c_int, flags: libc::c_int) -> libc::c_int;
+    pub fn timerfd_settime(fd: libc::c_int,
+                           flags: libc::c_int,
+                           new_value: *const itimerspec,
+                           old_value: *mut itimerspec) -> libc::c_int;
+
+    pub fn signalfd(fd: libc::c_int,
+                    mask: *const libc::sigset_t,
+                    flags: libc::c_int) -> libc::c_int;
+
+    pub fn inotify_init1(flags: libc::c_int) -> libc::c_int;
+    pub fn inotify_add_watch(fd: libc::c_int,
+                             pathname: *const libc::c_char,
+                             mask: u32) -> libc::c_int;
+
+    pub fn strftime(s: *mut libc::c_char,
+                    max: libc::size_t,
+                    format: *const libc::c_char,
+                    tm: *const libc::tm) -> libc::size_t;
+}
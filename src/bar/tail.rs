@@ -0,0 +1,194 @@
+use std::collections::BTreeMap;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::fs::{
+    self,
+    File,
+};
+use std::cmp;
+
+use rustc_serialize::json::{
+    Json,
+    ToJson,
+};
+
+use bar::{
+    Align,
+    Format,
+    Formatter,
+    Position,
+};
+use event::{
+    Event,
+    EventLoop,
+    Token,
+    Watch,
+};
+use pipe::{
+    self,
+    PipeWriter,
+};
+use util::{
+    self,
+    text,
+    Result,
+    Error,
+};
+
+/// How much of the end of a file is read to find its last line, in bytes.
+const TAIL_SIZE: u64 = 4096;
+
+/// A module showing the last line written to a file or named FIFO.
+#[derive(Debug)]
+pub struct Tail {
+    name: String,
+    path: PathBuf,
+    pos: Position,
+    src: Token,
+    /// Watches the file itself, for writers that append without ever closing it. Replaced
+    /// along with the file.
+    file_src: Option<Token>,
+    /// Keeps the FIFO open for writing, so its reader never sees the end of file.
+    writer: Option<PipeWriter>,
+    /// Set if the FIFO was created here, and so is removed on `stop`.
+    created: bool,
+    line: String,
+}
+
+impl Tail {
+    pub fn new(conf: &util::Tail, ev: &mut EventLoop) -> Result<Tail> {
+        let align: Align = try!(conf.align.parse());
+        let path = PathBuf::from(&conf.path);
+
+        let (src, writer, created) = if conf.fifo.unwrap_or(false) {
+            let (rd, wr, created) = try!(pipe::fifo(&path).map_err(|err| {
+                Error::new(format!("failed to open FIFO `{}`: {}", path.display(), err))
+            }));
+            (try!(ev.add_pipe(rd)), Some(wr), created)
+        } else {
+            // Watch the directory too, so the file can be created or replaced.
+            let dir = match path.parent() {
+                Some(dir) if dir != Path::new("") => dir.to_path_buf(),
+                _ => PathBuf::from("."),
+            };
+            (try!(ev.add_watch(&dir, Watch::Entries)), None, false)
+        };
+
+        let mut tail = Tail {
+            name: conf.name.clone(),
+            path: path,
+            pos: Position::new(align, conf.index),
+            src: src,
+            file_src: None,
+            writer: writer,
+            created: created,
+            line: String::new(),
+        };
+        if tail.writer.is_none() {
+            try!(tail.watch_file(ev));
+            tail.line = tail.read_last_line();
+        }
+        Ok(tail)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn position(&self) -> Position {
+        self.pos
+    }
+
+    /// Returns `true` if `event` came from one of this module's sources.
+    pub fn owns(&self, event: &Event) -> bool {
+        let token = event.token();
+        token == self.src || self.file_src == Some(token)
+    }
+
+    /// Handles an event from one of this module's sources, returning `true` if the line
+    /// changed.
+    pub fn handle(&mut self, ev: &mut EventLoop, event: Event) -> Result<bool> {
+        let line = match event {
+            Event::Line(_, line) => line,
+            Event::Changed(t, _) if self.file_src == Some(t) => self.read_last_line(),
+            Event::Changed(_, ref name) if name.as_ref().map(|n| &**n) == self.file_name() => {
+                // The file may be a new one, which the old watch knows nothing about.
+                try!(self.watch_file(ev));
+                self.read_last_line()
+            },
+            _ => return Ok(false),
+        };
+        if line == self.line {
+            return Ok(false);
+        }
+        self.line = line;
+        Ok(true)
+    }
+
+    /// Removes the module's sources from `ev`, and the FIFO if it was created here.
+    pub fn stop(&mut self, ev: &mut EventLoop) -> Result<()> {
+        if let Some(file_src) = self.file_src.take() {
+            try!(ev.remove(file_src));
+        }
+        try!(ev.remove(self.src));
+        if self.created {
+            self.created = false;
+            try!(fs::remove_file(&self.path));
+        }
+        Ok(())
+    }
+
+    /// Watches the file at `path`, replacing any previous watch. A file that doesn't exist yet
+    /// is watched once it is created.
+    fn watch_file(&mut self, ev: &mut EventLoop) -> Result<()> {
+        if let Some(file_src) = self.file_src.take() {
+            try!(ev.remove(file_src));
+        }
+        self.file_src = ev.add_watch(&self.path, Watch::File).ok();
+        Ok(())
+    }
+
+    fn file_name(&self) -> Option<&str> {
+        self.path.file_name().and_then(|f| f.to_str())
+    }
+
+    /// Reads the last non-empty line of the file, which may not exist (yet).
+    fn read_last_line(&self) -> String {
+        let mut file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(..) => return String::new(),
+        };
+        let len = file.metadata().map(|m| m.len()).unwrap_or(0);
+        let start = len - cmp::min(len, TAIL_SIZE);
+
+        let mut buf = Vec::new();
+        if file.seek(SeekFrom::Start(start)).and_then(|_| file.read_to_end(&mut buf)).is_err() {
+            return String::new();
+        }
+        String::from_utf8_lossy(&buf)
+            .lines()
+            .rev()
+            .find(|l| !l.trim().is_empty())
+            .map(String::from)
+            .unwrap_or(String::new())
+    }
+}
+
+impl Format for Tail {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<()> {
+        fmt.write(&*text::escape(&self.line))
+    }
+}
+
+impl ToJson for Tail {
+    fn to_json(&self) -> Json {
+        let mut obj = BTreeMap::new();
+        obj.insert(String::from("path"), self.path.to_string_lossy().into_owned().to_json());
+        obj.insert(String::from("line"), self.line.to_json());
+        Json::Object(obj)
+    }
+}
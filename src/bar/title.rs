@@ -0,0 +1,332 @@
+use std::process::{
+    Child,
+    Command,
+    Stdio,
+};
+use std::ascii::AsciiExt;
+use std::result;
+
+use rustc_serialize::json::{
+    Json,
+    ToJson,
+};
+
+use bar::{
+    Format,
+    Formatter,
+    Marquee,
+};
+use data::Provider;
+use event::{
+    Event,
+    EventLoop,
+    Token,
+};
+use pipe;
+use util::{
+    self,
+    text,
+    Result,
+    Error,
+    ParseError,
+    ParseErrorKind,
+};
+
+/// Steps to pause for at either end of a scrolling title, unless the config says otherwise.
+const MARQUEE_PAUSE: u32 = 5;
+
+/// The focused window's title.
+#[derive(Debug)]
+pub struct WindowTitle {
+    wm: Wm,
+    title: String,
+    max_width: Option<usize>,
+    /// Scrolls titles wider than `max_width`, rather than truncating them.
+    marquee: Option<Marquee<String>>,
+    rules: Vec<util::Rewrite>,
+    /// The node bspwm last reported as focused.
+    focused: Option<String>,
+    /// Follows the focused node, since bspwm doesn't report title changes itself.
+    spy: Option<Spy>,
+    /// Class and untouched title of the focused node, as `xprop` last reported them.
+    class: String,
+    name: String,
+}
+
+/// `xprop -spy` on a window, printing its class and title whenever either changes.
+#[derive(Debug)]
+struct Spy {
+    node: String,
+    child: Child,
+    src: Token,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Wm {
+    Bspwm,
+    I3,
+    Sway,
+}
+
+impl WindowTitle {
+    pub fn new(conf: &util::Title) -> Result<WindowTitle> {
+        let wm = match &*conf.wm {
+            "bspwm" => Wm::Bspwm,
+            "i3" => Wm::I3,
+            "sway" => Wm::Sway,
+            other => return Err(Error::new(format!("unknown window manager `{}`", other))),
+        };
+
+        Ok(WindowTitle {
+            wm: wm,
+            title: String::new(),
+            max_width: conf.max_width,
+            marquee: match (conf.max_width, conf.marquee.as_ref()) {
+                (Some(max), Some(marquee)) => {
+                    Some(Marquee::new(String::new(), max, marquee.pause.unwrap_or(MARQUEE_PAUSE)))
+                },
+                _ => None,
+            },
+            rules: conf.rewrite.clone().unwrap_or(Vec::new()),
+            focused: None,
+            spy: None,
+            class: String::new(),
+            name: String::new(),
+        })
+    }
+
+    /// Returns the command whose output this provider consumes.
+    pub fn command(&self) -> Command {
+        let mut cmd = match self.wm {
+            Wm::Bspwm => {
+                let mut cmd = Command::new("bspc");
+                cmd.arg("subscribe").arg("node_focus");
+                cmd
+            },
+            Wm::I3 | Wm::Sway => {
+                let mut cmd = Command::new(if self.wm == Wm::I3 { "i3-msg" } else { "swaymsg" });
+                cmd.arg("-t").arg("subscribe").arg("-m").arg("[\"window\"]");
+                cmd
+            },
+        };
+        cmd.stdin(Stdio::null())
+           .stderr(Stdio::inherit());
+        cmd
+    }
+
+    /// Starts following the focused node if it changed since the last call, replacing whatever
+    /// node was followed before.
+    ///
+    /// Only bspwm has nodes to follow; the other window managers report titles themselves.
+    pub fn follow(&mut self, ev: &mut EventLoop) -> Result<()> {
+        let node = match self.focused {
+            Some(ref node) => node.clone(),
+            None => return Ok(()),
+        };
+        if self.spy.as_ref().map_or(false, |s| s.node == node) {
+            return Ok(());
+        }
+        try!(self.stop(ev));
+
+        let (rd, wr) = try!(pipe::pipe());
+        let child = try!(Command::new("xprop")
+                         .arg("-spy")
+                         .arg("-id").arg(&node)
+                         .arg("WM_CLASS")
+                         .arg("_NET_WM_NAME")
+                         .stdin(Stdio::null())
+                         .stdout(try!(wr.stdio()))
+                         .stderr(Stdio::null())
+                         .spawn());
+        // Only the child may hold the write end, or the pipe never reports being closed.
+        drop(wr);
+
+        self.spy = Some(Spy {
+            node: node,
+            child: child,
+            src: try!(ev.add_pipe(rd)),
+        });
+        Ok(())
+    }
+
+    /// Returns `true` if `event` came from the `xprop` following the focused node.
+    pub fn owns(&self, event: &Event) -> bool {
+        self.spy.as_ref().map_or(false, |s| s.src == event.token())
+    }
+
+    /// Handles an event from the `xprop` following the focused node, returning `true` if the
+    /// title changed.
+    pub fn handle(&mut self, event: Event) -> bool {
+        let title = self.title.clone();
+        match event {
+            Event::Line(_, line) => self.consume_xprop(&line),
+            Event::Closed(..) => {
+                // `xprop` only quits on its own once the window is gone.
+                if let Some(mut spy) = self.spy.take() {
+                    let _ = spy.child.kill();
+                    let _ = spy.child.wait();
+                }
+                self.class.clear();
+                self.name.clear();
+                self.update(String::new());
+            },
+            _ => {},
+        }
+        title != self.title
+    }
+
+    /// Kills the `xprop` following the focused node, if any, and removes its source from `ev`.
+    pub fn stop(&mut self, ev: &mut EventLoop) -> Result<()> {
+        if let Some(mut spy) = self.spy.take() {
+            let _ = spy.child.kill();
+            let _ = spy.child.wait();
+            try!(ev.remove(spy.src));
+        }
+        Ok(())
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    /// Scrolls the title one step, returning `true` if what is shown changed.
+    pub fn step(&mut self) -> bool {
+        self.marquee.as_mut().map_or(false, |m| m.step())
+    }
+
+    fn update(&mut self, title: String) {
+        if title == self.title {
+            return;
+        }
+        if let Some(ref mut marquee) = self.marquee {
+            marquee.set(text::escape(&title));
+        }
+        self.title = title;
+    }
+
+    fn set_title(&mut self, app: &str, title: &str) {
+        let mut title = String::from(title);
+        for rule in self.rules.iter().filter(|r| r.app.eq_ignore_ascii_case(app)) {
+            let replace = rule.replace.as_ref().map(|s| &**s).unwrap_or("");
+            title = match rule.find {
+                Some(ref find) => title.replace(&**find, replace),
+                None => String::from(replace),
+            };
+        }
+        self.update(title);
+    }
+
+    fn consume_bspwm(&mut self, data: &str) -> result::Result<(), ParseError> {
+        // `node_focus <monitor_id> <desktop_id> <node_id>`
+        let node = try!(data.split_whitespace().nth(3)
+                        .ok_or(ParseError::with_field(data, "node_id",
+                                                      ParseErrorKind::MissingValue)));
+        // The title is picked up by `follow`, so as not to wait on `xprop` here.
+        self.focused = Some(String::from(node));
+        Ok(())
+    }
+
+    /// Handles a line printed by `xprop -spy`, e.g. `_NET_WM_NAME(UTF8_STRING) = "title"`.
+    fn consume_xprop(&mut self, line: &str) {
+        let mid = match line.find(" = ") {
+            Some(mid) => mid,
+            None => return,
+        };
+        let values = parse_xprop_strings(&line[mid+3..]);
+
+        if line.starts_with("WM_CLASS") {
+            // Instance name first, then the class name.
+            self.class = values.into_iter().nth(1).unwrap_or(String::new());
+        } else if line.starts_with("_NET_WM_NAME") {
+            self.name = values.into_iter().next().unwrap_or(String::new());
+        } else {
+            return;
+        }
+        let (class, name) = (self.class.clone(), self.name.clone());
+        self.set_title(&class, &name);
+    }
+
+    fn consume_i3(&mut self, data: &str) -> result::Result<(), ParseError> {
+        let json = try!(Json::from_str(data)
+                        .map_err(|_| ParseError::new(data, ParseErrorKind::InvalidJson)));
+        let change = json.find("change").and_then(|c| c.as_string()).unwrap_or("");
+        let container = try!(json.find("container")
+                             .ok_or(ParseError::with_field(data, "container",
+                                                           ParseErrorKind::MissingValue)));
+        let focused = container.find("focused").and_then(|f| f.as_boolean()).unwrap_or(false);
+
+        match change {
+            "focus" => {},
+            "title" if focused => {},
+            "close" if focused => {
+                self.update(String::new());
+                return Ok(());
+            },
+            _ => return Ok(()),
+        }
+
+        let title = container.find("name").and_then(|n| n.as_string()).unwrap_or("");
+        // Native wayland clients only have an `app_id`.
+        let app = container.find("app_id")
+            .and_then(|a| a.as_string())
+            .or_else(|| container.find_path(&["window_properties", "class"])
+                     .and_then(|c| c.as_string()))
+            .unwrap_or("");
+        self.set_title(app, title);
+        Ok(())
+    }
+}
+
+impl Format for WindowTitle {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<()> {
+        if let Some(ref marquee) = self.marquee {
+            return fmt.write(marquee);
+        }
+        let title = match self.max_width {
+            Some(max) => text::truncate(&self.title, max),
+            None => self.title.clone(),
+        };
+        fmt.write(&*text::escape(&title))
+    }
+}
+
+impl ToJson for WindowTitle {
+    fn to_json(&self) -> Json {
+        self.title.to_json()
+    }
+}
+
+impl Provider for WindowTitle {
+    fn consume(&mut self, data: &str) -> result::Result<(), ParseError> {
+        match self.wm {
+            Wm::Bspwm => self.consume_bspwm(data),
+            Wm::I3 | Wm::Sway => self.consume_i3(data),
+        }
+    }
+}
+
+/// Parses a comma-separated list of quoted, backslash-escaped strings.
+fn parse_xprop_strings(data: &str) -> Vec<String> {
+    let mut values = Vec::new();
+    let mut chars = data.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '"' {
+            continue;
+        }
+        let mut value = String::new();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => {
+                    if let Some(c) = chars.next() {
+                        value.push(c);
+                    }
+                },
+                '"' => break,
+                c => value.push(c),
+            }
+        }
+        values.push(value);
+    }
+    values
+}
@@ -2,30 +2,62 @@ use std::path::{
     PathBuf,
     Path,
 };
+use std::collections::BTreeMap;
 use std::env;
+use std::char;
 use std::fs::File;
 use std::io::prelude::*;
 
 use toml;
 
+use bar::Align;
+use data::{
+    HISTORY_LEN,
+    SERIES,
+};
+use expr::Program;
 use util::{
     Result,
     Error,
 };
 
-#[derive(Debug, Clone, RustcDecodable)]
+#[derive(Debug, Clone, PartialEq, RustcDecodable)]
 pub struct Config {
     pub fonts: Vec<String>,
     pub geom: Geometry,
     pub color: Color,
+    /// Minimum time between frames, in milliseconds. Updates arriving in between are drawn
+    /// together. Defaults to 16.
+    pub frame_interval: Option<u64>,
+    pub intervals: Option<Intervals>,
+    pub layout: Option<Layout>,
+    pub decorations: Option<Decorations>,
+    pub battery: Option<Battery>,
+    pub alerts: Option<Vec<Alert>>,
+    pub hook: Option<Vec<Hook>>,
+    pub title: Option<Title>,
+    pub slots: Option<Vec<Slot>>,
+    pub scripts: Option<Vec<Script>>,
+    pub execs: Option<Vec<Exec>>,
+    pub tails: Option<Vec<Tail>>,
+    pub sparklines: Option<Vec<Sparkline>>,
+    pub backlights: Option<Vec<Backlight>>,
+    pub mpd: Option<Mpd>,
+    pub plugins: Option<Plugins>,
+    pub formats: Option<Vec<ModuleFormat>>,
 }
 
 impl Config {
     pub fn new() -> Result<Config> {
-        let path = get_conf_path();
+        let path = Config::default_path();
         Config::from_path(path)
     }
 
+    /// The path `Config::new` reads from.
+    pub fn default_path() -> PathBuf {
+        get_conf_path()
+    }
+
     pub fn from_path<P>(path: P) -> Result<Config>
         where P: AsRef<Path>
     {
@@ -33,8 +65,170 @@ impl Config {
         let mut buf = String::new();
         try!(cfile.read_to_string(&mut buf));
 
-        toml::decode_str(&buf).ok_or(Error::new("config parse error"))
+        let conf: Config = try!(toml::decode_str(&buf).ok_or(Error::new("config parse error")));
+        try!(conf.validate());
+        Ok(conf)
     }
+
+    /// Checks for values that decode fine but make no sense.
+    pub fn validate(&self) -> Result<()> {
+        if self.geom.size[1] == 0 {
+            return Err(Error::new("config error: bar height must be non-zero"));
+        }
+        for color in &[&self.color.fg, &self.color.bg] {
+            if !is_color(color) {
+                return Err(Error::new(format!("config error: invalid color `{}`", color)));
+            }
+        }
+        // Modules are addressed by name, so the names have to be unique between them.
+        let slots = self.slots.as_ref().map(|s| &**s).unwrap_or(&[]);
+        let scripts = self.scripts.as_ref().map(|s| &**s).unwrap_or(&[]);
+        let execs = self.execs.as_ref().map(|e| &**e).unwrap_or(&[]);
+        let tails = self.tails.as_ref().map(|t| &**t).unwrap_or(&[]);
+        let sparklines = self.sparklines.as_ref().map(|s| &**s).unwrap_or(&[]);
+        let backlights = self.backlights.as_ref().map(|b| &**b).unwrap_or(&[]);
+        let mpd = self.mpd.as_ref().map(|m| (&*m.name, &*m.align));
+        let plugged = self.plugins.as_ref().map(|p| &*p.modules).unwrap_or(&[]);
+        let modules: Vec<(&str, &str)> = slots.iter()
+            .map(|s| (&*s.name, &*s.align))
+            .chain(scripts.iter().map(|s| (&*s.name, &*s.align)))
+            .chain(execs.iter().map(|e| (&*e.name, &*e.align)))
+            .chain(tails.iter().map(|t| (&*t.name, &*t.align)))
+            .chain(sparklines.iter().map(|s| (&*s.name, &*s.align)))
+            .chain(backlights.iter().map(|b| (&*b.name, &*b.align)))
+            .chain(mpd)
+            .chain(plugged.iter().map(|p| (&*p.name, &*p.align)))
+            .collect();
+        for (i, &(name, align)) in modules.iter().enumerate() {
+            if modules[..i].iter().any(|&(n, _)| n == name) {
+                return Err(Error::new(format!("config error: duplicate module `{}`", name)));
+            }
+            if let Err(err) = align.parse::<Align>() {
+                return Err(Error::new(format!("config error: {}", err)));
+            }
+        }
+        if let Some(ref intervals) = self.intervals {
+            if intervals.date == Some(0) || intervals.battery == Some(0) {
+                return Err(Error::new("config error: intervals must be non-zero"));
+            }
+        }
+        for exec in execs {
+            if exec.interval == 0 {
+                return Err(Error::new(format!("config error: interval of `{}` must be non-zero",
+                                              exec.name)));
+            }
+        }
+        if let Some(ref layout) = self.layout {
+            // Lemonbar takes a width of 0 to mean the whole screen, which a layout can't know.
+            if self.geom.size[0] == 0 {
+                return Err(Error::new("config error: `layout` needs a non-zero bar width"));
+            }
+            if layout.char_widths.len() != self.fonts.len() {
+                return Err(Error::new("config error: `char_widths` must list a width for each \
+                                       font"));
+            }
+            if layout.char_widths.contains(&0) {
+                return Err(Error::new("config error: character widths must be non-zero"));
+            }
+        }
+        if let Some(ref decorations) = self.decorations {
+            if decorations.separator.is_some() && decorations.arrows.unwrap_or(false) {
+                return Err(Error::new("config error: `separator` and `arrows` are exclusive"));
+            }
+        }
+        for sparkline in sparklines {
+            if !SERIES.contains(&&*sparkline.series) {
+                return Err(Error::new(format!("config error: unknown series `{}`, expected one \
+                                               of {}", sparkline.series, SERIES.join(", "))));
+            }
+            if sparkline.width == 0 || sparkline.width > HISTORY_LEN {
+                return Err(Error::new(format!("config error: width of `{}` must be between 1 \
+                                               and {}", sparkline.name, HISTORY_LEN)));
+            }
+        }
+        if let Some(ref battery) = self.battery {
+            if let Some(ref points) = battery.breakpoints {
+                if points.len() != 3 || points[0] > points[1] || points[1] > points[2] ||
+                   points[2] > 100 {
+                    return Err(Error::new("config error: battery breakpoints must be three \
+                                           ascending percentages"));
+                }
+            }
+            if let Some(ref icons) = battery.icons {
+                let codepoints = [icons.empty, icons.low, icons.half, icons.full, icons.charging];
+                for &cp in codepoints.iter().filter_map(|cp| cp.as_ref()) {
+                    if char::from_u32(cp).is_none() {
+                        return Err(Error::new(format!("config error: invalid codepoint {:#x}",
+                                                      cp)));
+                    }
+                }
+            }
+        }
+        for alert in self.alerts.as_ref().map(|a| &**a).unwrap_or(&[]) {
+            if alert.source != "battery" && alert.source != "temp" {
+                return Err(Error::new(format!("config error: unknown alert source `{}`",
+                                              alert.source)));
+            }
+            if alert.below.is_some() == alert.above.is_some() {
+                return Err(Error::new("config error: an alert needs exactly one of `below` and \
+                                       `above`"));
+            }
+            if alert.hysteresis.map_or(false, |h| h < 0.0) {
+                return Err(Error::new("config error: hysteresis must not be negative"));
+            }
+        }
+        for backlight in backlights {
+            if backlight.step.map_or(false, |s| s == 0 || s > 100) {
+                return Err(Error::new(format!("config error: step of `{}` must be between 1 and \
+                                               100", backlight.name)));
+            }
+            for &cp in backlight.icons.as_ref().map(|i| &**i).unwrap_or(&[]) {
+                if char::from_u32(cp).is_none() {
+                    return Err(Error::new(format!("config error: invalid codepoint {:#x}", cp)));
+                }
+            }
+        }
+        for format in self.formats.as_ref().map(|f| &**f).unwrap_or(&[]) {
+            if let Err(err) = format.script.parse::<Program>() {
+                return Err(Error::new(format!("config error: format of `{}`: {}",
+                                              format.module, err)));
+            }
+        }
+        for hook in self.hook.as_ref().map(|h| &**h).unwrap_or(&[]) {
+            for script in hook.value.iter().chain(hook.condition.iter()) {
+                if let Err(err) = script.parse::<Program>() {
+                    return Err(Error::new(format!("config error: hook on `{}`: {}",
+                                                  hook.module, err)));
+                }
+            }
+        }
+        if let Some(ref title) = self.title {
+            match &*title.wm {
+                "bspwm" | "i3" | "sway" => {},
+                wm => {
+                    return Err(Error::new(format!("config error: unknown window manager `{}`",
+                                                  wm)));
+                },
+            }
+            if let Some(ref marquee) = title.marquee {
+                if title.max_width.is_none() {
+                    return Err(Error::new("config error: a scrolling title needs a `max_width`"));
+                }
+                if marquee.step == Some(0) {
+                    return Err(Error::new("config error: marquee step must be non-zero"));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns `true` if `s` is a color `lemonbar` understands, i.e. `#rgb`, `#rrggbb` or
+/// `#aarrggbb`.
+pub fn is_color(s: &str) -> bool {
+    s.starts_with('#') &&
+        [4, 7, 9].contains(&s.len()) &&
+        s[1..].chars().all(|c| c.is_digit(16))
 }
 
 fn get_conf_path() -> PathBuf {
@@ -49,15 +243,275 @@ fn get_conf_path() -> PathBuf {
     }
 }
 
-#[derive(Debug, Clone, RustcDecodable)]
+#[derive(Debug, Clone, PartialEq, RustcDecodable)]
 pub struct Geometry {
     pub size: [u32; 2],
     pub offset: [u32; 2],
 }
 
-#[derive(Debug, Clone, RustcDecodable)]
+#[derive(Debug, Clone, PartialEq, RustcDecodable)]
 pub struct Color {
     pub fg: String,
     pub bg: String,
 }
 
+/// Lets modules that don't fit be shortened or hidden, rather than overlapping.
+#[derive(Debug, Clone, PartialEq, RustcDecodable)]
+pub struct Layout {
+    /// Width of a character in each font, in pixels, in the same order as `fonts`.
+    pub char_widths: Vec<u32>,
+    /// Priorities of modules by name. When the bar is too narrow, the modules with the lowest
+    /// priority are shortened or hidden first. Defaults to 0.
+    pub priorities: Option<BTreeMap<String, i32>>,
+}
+
+/// What is drawn between modules and around sections. All text may contain formatting tags,
+/// so a literal `%` has to be written `%%`.
+#[derive(Debug, Clone, PartialEq, RustcDecodable)]
+pub struct Decorations {
+    /// Drawn between adjacent modules of a section.
+    pub separator: Option<String>,
+    /// Draw powerline-style arrows between modules instead, in their background colors.
+    pub arrows: Option<bool>,
+    pub left: Option<SectionEnds>,
+    pub center: Option<SectionEnds>,
+    pub right: Option<SectionEnds>,
+}
+
+/// Drawn at either end of a section, as long as it has a module to show.
+#[derive(Debug, Clone, PartialEq, RustcDecodable)]
+pub struct SectionEnds {
+    pub start: Option<String>,
+    pub end: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, RustcDecodable)]
+pub struct Title {
+    /// One of `bspwm`, `i3` or `sway`.
+    pub wm: String,
+    /// Maximum width of the title, in columns.
+    pub max_width: Option<usize>,
+    /// Scroll titles wider than `max_width` rather than truncating them.
+    pub marquee: Option<Marquee>,
+    pub rewrite: Option<Vec<Rewrite>>,
+}
+
+/// Seconds between updates of the modules the bar polls itself. Each defaults to 1.
+#[derive(Debug, Clone, PartialEq, RustcDecodable)]
+pub struct Intervals {
+    pub date: Option<u64>,
+    pub battery: Option<u64>,
+}
+
+/// How the battery is drawn.
+#[derive(Debug, Clone, PartialEq, RustcDecodable)]
+pub struct Battery {
+    /// Percentages from which the low, half and full icons are shown. Defaults to
+    /// `[10, 40, 80]`.
+    pub breakpoints: Option<Vec<usize>>,
+    /// Fill up the icon while charging, rather than showing the charging icon.
+    pub animate: Option<bool>,
+    pub icons: Option<BatteryIcons>,
+}
+
+/// Codepoints of the battery icons, for icon fonts other than the default one.
+#[derive(Debug, Clone, PartialEq, RustcDecodable)]
+pub struct BatteryIcons {
+    pub empty: Option<u32>,
+    pub low: Option<u32>,
+    pub half: Option<u32>,
+    pub full: Option<u32>,
+    pub charging: Option<u32>,
+}
+
+/// A command run once a value crosses a threshold. It gets the value in `BAR_VALUE` and the
+/// threshold in `BAR_THRESHOLD`.
+#[derive(Debug, Clone, PartialEq, RustcDecodable)]
+pub struct Alert {
+    /// Either `battery`, the charge in percent while discharging, or `temp`, the CPU
+    /// temperature.
+    pub source: String,
+    /// Fire when the value drops below this.
+    pub below: Option<f64>,
+    /// Fire when the value rises above this.
+    pub above: Option<f64>,
+    /// How far the value has to move back before the alert can fire again. Defaults to 2.
+    pub hysteresis: Option<f64>,
+    /// Run with `sh -c`.
+    pub command: String,
+}
+
+/// A command run when a value derived from a module's data changes. See `hook` for details.
+///
+/// The command gets the module's name in `BAR_MODULE`, and the previous and current values in
+/// `BAR_OLD` and `BAR_NEW`.
+#[derive(Debug, Clone, PartialEq, RustcDecodable)]
+pub struct Hook {
+    /// Name of the module, as listed by `bar state`.
+    pub module: String,
+    /// Script picking the value to watch out of the module's data. Defaults to all of it.
+    pub value: Option<String>,
+    /// Script deciding whether a change fires the hook, with `old` and `new` in scope.
+    pub condition: Option<String>,
+    /// Run with `sh -c`.
+    pub command: String,
+    /// Steps the scripts may take before they are abandoned. Defaults to 1000.
+    pub max_steps: Option<usize>,
+}
+
+/// Scrolling of text too wide to show at once.
+#[derive(Debug, Clone, PartialEq, RustcDecodable)]
+pub struct Marquee {
+    /// Milliseconds between steps of one grapheme. Defaults to 200.
+    pub step: Option<u64>,
+    /// Steps to pause for at either end. Defaults to 5.
+    pub pause: Option<u32>,
+}
+
+/// Rewrites the titles of windows belonging to `app`.
+///
+/// Occurrences of `find` are replaced with `replace`. If `find` is absent the whole title is
+/// replaced.
+#[derive(Debug, Clone, PartialEq, RustcDecodable)]
+pub struct Rewrite {
+    pub app: String,
+    pub find: Option<String>,
+    pub replace: Option<String>,
+}
+
+/// A named slot whose text is set over the control socket.
+#[derive(Debug, Clone, PartialEq, RustcDecodable)]
+pub struct Slot {
+    pub name: String,
+    /// One of `left`, `center` or `right`.
+    pub align: String,
+    /// Position within the alignment, from left to right.
+    pub index: usize,
+    /// Text shown until something else is set.
+    pub text: Option<String>,
+}
+
+/// An external command rendering a module, one JSON object per line.
+#[derive(Debug, Clone, PartialEq, RustcDecodable)]
+pub struct Script {
+    pub name: String,
+    /// Run with `sh -c`.
+    pub command: String,
+    /// One of `left`, `center` or `right`.
+    pub align: String,
+    /// Position within the alignment, from left to right.
+    pub index: usize,
+}
+
+/// A command run periodically, showing its output.
+#[derive(Debug, Clone, PartialEq, RustcDecodable)]
+pub struct Exec {
+    pub name: String,
+    /// Run with `sh -c`.
+    pub command: String,
+    /// One of `left`, `center` or `right`.
+    pub align: String,
+    /// Position within the alignment, from left to right.
+    pub index: usize,
+    /// Seconds between runs.
+    pub interval: u64,
+    /// Seconds a run may take before it is killed. Defaults to `interval`.
+    pub timeout: Option<u64>,
+    /// Show failed runs, rather than the output of the last successful one.
+    pub show_errors: Option<bool>,
+}
+
+/// A file, or named FIFO, whose last line is shown.
+#[derive(Debug, Clone, PartialEq, RustcDecodable)]
+pub struct Tail {
+    pub name: String,
+    pub path: String,
+    /// One of `left`, `center` or `right`.
+    pub align: String,
+    /// Position within the alignment, from left to right.
+    pub index: usize,
+    /// Read from a named FIFO at `path`, creating it if needed, rather than watching a file.
+    pub fifo: Option<bool>,
+}
+
+/// A graph of the recent history of a series.
+#[derive(Debug, Clone, PartialEq, RustcDecodable)]
+pub struct Sparkline {
+    pub name: String,
+    /// One of `cpu`, `cpu0` to `cpu3`, `mem`, `net_down` or `net_up`.
+    pub series: String,
+    /// One of `left`, `center` or `right`.
+    pub align: String,
+    /// Position within the alignment, from left to right.
+    pub index: usize,
+    /// Number of samples shown.
+    pub width: usize,
+    /// Value drawn as a full block. Defaults to the largest sample shown.
+    pub max: Option<f64>,
+}
+
+/// The brightness of a backlight, adjusted by scrolling over it.
+#[derive(Debug, Clone, PartialEq, RustcDecodable)]
+pub struct Backlight {
+    pub name: String,
+    /// One of `left`, `center` or `right`.
+    pub align: String,
+    /// Position within the alignment, from left to right.
+    pub index: usize,
+    /// Directory of the device. Defaults to the one in `/sys/class/backlight` its type makes
+    /// most likely to control the screen.
+    pub device: Option<String>,
+    /// Percent each scroll adjusts the brightness by. Defaults to 5.
+    pub step: Option<u32>,
+    /// Run with `sh -c` to adjust the brightness, rather than writing to the device. Gets the
+    /// new brightness in `BAR_BRIGHTNESS`, and in percent in `BAR_PCT`.
+    pub helper: Option<String>,
+    /// Codepoints of icons from dim to bright, shown before the percentage.
+    pub icons: Option<Vec<u32>>,
+}
+
+/// What MPD is playing.
+#[derive(Debug, Clone, PartialEq, RustcDecodable)]
+pub struct Mpd {
+    pub name: String,
+    /// One of `left`, `center` or `right`.
+    pub align: String,
+    /// Position within the alignment, from left to right.
+    pub index: usize,
+    /// Either `host:port` or the path of a Unix socket. Defaults to what `MPD_HOST` and
+    /// `MPD_PORT` say, or `localhost:6600`.
+    pub address: Option<String>,
+    /// Maximum width of the artist and title, in columns.
+    pub max_width: Option<usize>,
+}
+
+/// Modules loaded from shared libraries.
+#[derive(Debug, Clone, PartialEq, RustcDecodable)]
+pub struct Plugins {
+    /// Directory every `.so` file in is loaded as a plugin.
+    pub dir: String,
+    pub modules: Vec<PluginModule>,
+}
+
+/// Places a module provided by a plugin.
+#[derive(Debug, Clone, PartialEq, RustcDecodable)]
+pub struct PluginModule {
+    /// Name the plugin registered the module under.
+    pub name: String,
+    /// One of `left`, `center` or `right`.
+    pub align: String,
+    /// Position within the alignment, from left to right.
+    pub index: usize,
+    /// Command whose output is fed to the module, run with `sh -c`.
+    pub command: Option<String>,
+}
+
+/// A script deciding how a module is displayed. See `expr` for the language.
+#[derive(Debug, Clone, PartialEq, RustcDecodable)]
+pub struct ModuleFormat {
+    /// Name of the module, as listed by `bar state`.
+    pub module: String,
+    pub script: String,
+    /// Steps the script may take before it is abandoned. Defaults to 1000.
+    pub max_steps: Option<usize>,
+}
@@ -37,8 +37,96 @@ impl From<fmt::Error> for Error {
     }
 }
 
+impl From<ParseError> for Error {
+    fn from(err: ParseError) -> Error {
+        Error::new(err)
+    }
+}
+
 impl fmt::Display for Error {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         self.0.fmt(fmt)
     }
 }
+
+/// An error encountered while parsing a line of provider output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    line: String,
+    field: Option<String>,
+    kind: ParseErrorKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    /// An expected delimiter was not found.
+    MissingDelimiter(char),
+    /// A required value was absent.
+    MissingValue,
+    /// A value could not be parsed as a number.
+    InvalidNumber,
+    /// The line was not valid JSON.
+    InvalidJson,
+    /// A value was not one of those expected.
+    UnknownValue,
+    /// The consumer refused the line without saying why.
+    Rejected,
+}
+
+impl ParseError {
+    pub fn new(line: &str, kind: ParseErrorKind) -> ParseError {
+        ParseError {
+            line: String::from(line),
+            field: None,
+            kind: kind,
+        }
+    }
+
+    pub fn with_field(line: &str, field: &str, kind: ParseErrorKind) -> ParseError {
+        ParseError {
+            line: String::from(line),
+            field: Some(String::from(field)),
+            kind: kind,
+        }
+    }
+
+    /// The offending line.
+    pub fn line(&self) -> &str {
+        &self.line
+    }
+
+    /// The field being parsed, if known.
+    pub fn field(&self) -> Option<&str> {
+        self.field.as_ref().map(|f| &**f)
+    }
+
+    pub fn kind(&self) -> ParseErrorKind {
+        self.kind
+    }
+}
+
+impl StdError for ParseError {
+    fn description(&self) -> &str {
+        match self.kind {
+            ParseErrorKind::MissingDelimiter(..) => "missing delimiter",
+            ParseErrorKind::MissingValue => "missing value",
+            ParseErrorKind::InvalidNumber => "invalid number",
+            ParseErrorKind::InvalidJson => "invalid json",
+            ParseErrorKind::UnknownValue => "unknown value",
+            ParseErrorKind::Rejected => "rejected",
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        try!(match self.kind {
+            ParseErrorKind::MissingDelimiter(c) => write!(fmt, "missing delimiter `{}`", c),
+            _ => fmt.write_str(self.description()),
+        });
+        if let Some(ref field) = self.field {
+            try!(write!(fmt, " in field `{}`", field));
+        }
+        write!(fmt, ": `{}`", self.line)
+    }
+}
@@ -1,13 +1,39 @@
 mod errors;
 mod config;
+pub mod text;
+#[cfg(test)]
+pub mod testing;
 
 pub use self::errors::{
     Error,
     Result,
+    ParseError,
+    ParseErrorKind,
 };
 
 pub use self::config::{
     Config,
     Geometry,
     Color,
+    Layout,
+    Decorations,
+    Battery,
+    BatteryIcons,
+    Alert,
+    Hook,
+    SectionEnds,
+    Title,
+    Marquee,
+    Rewrite,
+    Slot,
+    Script,
+    Exec,
+    Tail,
+    Sparkline,
+    Backlight,
+    Mpd,
+    Plugins,
+    PluginModule,
+    ModuleFormat,
+    is_color,
 };
@@ -0,0 +1,42 @@
+//! Helpers for unit tests.
+
+use std::env;
+use std::fs;
+use std::path::{
+    Path,
+    PathBuf,
+};
+
+use libc;
+
+/// A path in the temporary directory, unique to the test process and `name`. Whatever ends up
+/// there is removed when dropped.
+pub struct TempPath {
+    path: PathBuf,
+}
+
+impl TempPath {
+    /// Reserves a path, removing anything a previous run left behind.
+    pub fn new(name: &str) -> TempPath {
+        let pid = unsafe { libc::getpid() };
+        let tmp = TempPath {
+            path: env::temp_dir().join(format!("bar-test-{}-{}", pid, name)),
+        };
+        tmp.remove();
+        tmp
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn remove(&self) {
+        let _ = fs::remove_dir_all(&self.path).or_else(|_| fs::remove_file(&self.path));
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        self.remove();
+    }
+}
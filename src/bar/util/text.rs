@@ -0,0 +1,42 @@
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// Ends text that was cut short.
+pub const ELLIPSIS: char = '\u{2026}';
+
+/// Returns the display width of `s`, in columns.
+pub fn width(s: &str) -> usize {
+    UnicodeWidthStr::width(s)
+}
+
+/// Truncates `s` to at most `max` columns, ending it with an ellipsis if anything was cut.
+///
+/// Truncation happens on grapheme boundaries, so combining sequences and wide characters are
+/// never split.
+pub fn truncate(s: &str, max: usize) -> String {
+    if width(s) <= max {
+        return String::from(s);
+    }
+    if max == 0 {
+        return String::new();
+    }
+
+    let mut out = String::new();
+    let mut used = 0;
+    for g in s.graphemes(true) {
+        let w = width(g);
+        // Leave room for the ellipsis.
+        if used + w + 1 > max {
+            break;
+        }
+        out.push_str(g);
+        used += w;
+    }
+    out.push(ELLIPSIS);
+    out
+}
+
+/// Escapes `s` so lemonbar displays it verbatim rather than interpreting `%{...}` blocks.
+pub fn escape(s: &str) -> String {
+    s.replace("%", "%%")
+}
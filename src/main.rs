@@ -3,36 +3,100 @@ extern crate toml;
 extern crate libc;
 extern crate rustc_serialize;
 
-use std::io::prelude::*;
-use std::io::{
-    BufReader,
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::char;
+use std::collections::BTreeMap;
+use std::os::unix::io::AsRawFd;
+use std::process::{
+    Command,
+    Stdio,
+};
+use std::time::{
+    Duration,
+    Instant,
+};
+
+use rustc_serialize::json::{
+    Json,
+    ToJson,
 };
 
 use bar::pipe;
+use bar::sys;
+use bar::event::{
+    Event,
+    EventLoop,
+    Token,
+    Watch,
+};
 use bar::{
     Bar,
     Align,
+    Layout,
+    Decorations,
+    Ends,
+    Separator,
     Position,
 };
-use bar::util::Result;
-use bar::util::Config;
+use bar::control::{
+    self,
+    Client,
+    Server,
+};
+use bar::supervisor::{
+    ChildId,
+    Supervisor,
+};
+use bar::util::{
+    text,
+    Error,
+    Result,
+    Config,
+    SectionEnds,
+};
 use bar::data::{
     Provider,
+    BatteryStyle,
+    Sparkline,
     System,
     WindowManager,
 };
+use bar::title::WindowTitle;
+use bar::script::Script;
+use bar::exec::Exec;
+use bar::tail::Tail;
+use bar::backlight::Backlight;
+use bar::mpd::Mpd;
+use bar::alert::Alert;
+use bar::hook::Hook;
+use bar::plugin;
+use bar::expr::Program;
 
 static USAGE: &'static str = "
 bar
 
 Usage:
     bar [options]
+    bar msg <command> [<args>...]
+    bar state [--json]
 
 Options:
     -c, --config=<path>     Specify config file path.
+    --json                  Print the state as JSON.
     -h, --help              Display this message.
     -v, --verbose           Print version info and exit.
 
+Messages:
+    set <slot> <text>       Set the text of a slot.
+    hide <module>           Hide a module.
+    show <module>           Show a hidden module.
+    refresh                 Redraw the bar.
+    toggle                  Hide or show the whole bar.
+    state [--json]          Describe every module.
+
 Configuration:
     Default config path is `$XDG_CONFIG_HOME/bar/bar.toml`.
 
@@ -41,52 +105,1384 @@ Configuration:
 #[derive(Debug, Clone, RustcDecodable)]
 pub struct Args {
     flag_config: Option<String>,
+    cmd_msg: bool,
+    cmd_state: bool,
+    flag_json: bool,
+    arg_command: Option<String>,
+    arg_args: Vec<String>,
 }
 
 fn main() {
     bar::execute(execute, USAGE);
 }
 
-fn execute(args: Args) -> Result<()> {
-    let conf = if let Some(path) = args.flag_config {
-        try!(Config::from_path(path))
-    } else {
-        try!(Config::new())
+fn execute(args: Args) -> Result<i32> {
+    if args.cmd_msg {
+        return msg(args);
+    }
+    if args.cmd_state {
+        return send(&control::Command::State(args.flag_json));
+    }
+
+    let path = match args.flag_config {
+        Some(path) => PathBuf::from(path),
+        None => Config::default_path(),
     };
-    let args = gen_args(&conf);
+    let conf = try!(Config::from_path(&path));
 
-    let (rd_pipe, wr_pipe) = try!(pipe::pipe());
+    let mut app = try!(App::new(path, conf));
+    app.run()
+}
 
-    let mut bar = try!(Bar::new(&wr_pipe, &args));
+/// Sends a message to a running instance over the control socket.
+fn msg(args: Args) -> Result<i32> {
+    let mut line = args.arg_command.unwrap_or(String::new());
+    for arg in &args.arg_args {
+        line.push(' ');
+        line.push_str(arg);
+    }
+    let cmd: control::Command = try!(line.parse());
+    send(&cmd)
+}
 
-    let mut sys = try!(System::new(&wr_pipe));
-    let mut wm = try!(WindowManager::new(&wr_pipe));
-    let input = BufReader::new(rd_pipe);
+fn send(cmd: &control::Command) -> Result<i32> {
+    let resp = try!(control::send(&control::socket_path(), cmd));
+    print!("{}", resp);
+    Ok(0)
+}
 
-    for line in input.lines() {
-        let line = try!(line);
+// Where each module is placed.
+fn date_pos() -> Position { Position::right() }
+fn bat_pos() -> Position { Position::left() }
+fn cpu_pos() -> Position { Position::new(Align::Left, 1) }
+fn wm_pos() -> Position { Position::center() }
+fn title_pos() -> Position { Position::new(Align::Center, 1) }
 
-        if wm.is_data(&line) {
-            wm.consume(&line);
-        } else if sys.is_data(&line) {
-            sys.consume(&line);
+/// The names modules are addressed by over the control socket.
+fn module_names() -> Vec<(&'static str, Position)> {
+    vec![
+        ("date", date_pos()),
+        ("battery", bat_pos()),
+        ("cpu", cpu_pos()),
+        ("desktops", wm_pos()),
+        ("title", title_pos()),
+    ]
+}
+
+/// Makes sure no two modules are placed in the same spot, where one would silently replace the
+/// other.
+fn check_positions(conf: &Config) -> Result<()> {
+    let mut taken: Vec<(String, Position)> = module_names().into_iter()
+        .filter(|&(name, _)| name != "title" || conf.title.is_some())
+        .map(|(name, pos)| (String::from(name), pos))
+        .collect();
+
+    let placed = conf.slots.iter().flat_map(|s| s.iter()).map(|s| (&*s.name, &*s.align, s.index))
+        .chain(conf.scripts.iter().flat_map(|s| s.iter()).map(|s| (&*s.name, &*s.align, s.index)))
+        .chain(conf.execs.iter().flat_map(|e| e.iter()).map(|e| (&*e.name, &*e.align, e.index)))
+        .chain(conf.tails.iter().flat_map(|t| t.iter()).map(|t| (&*t.name, &*t.align, t.index)))
+        .chain(conf.sparklines.iter().flat_map(|s| s.iter())
+               .map(|s| (&*s.name, &*s.align, s.index)))
+        .chain(conf.backlights.iter().flat_map(|b| b.iter())
+               .map(|b| (&*b.name, &*b.align, b.index)))
+        .chain(conf.mpd.iter().map(|m| (&*m.name, &*m.align, m.index)))
+        .chain(conf.plugins.iter().flat_map(|p| p.modules.iter())
+               .map(|p| (&*p.name, &*p.align, p.index)));
+    for (name, align, index) in placed {
+        let pos = Position::new(try!(align.parse()), index);
+        if let Some(&(ref other, _)) = taken.iter().find(|&&(_, p)| p == pos) {
+            return Err(Error::new(format!("config error: `{}` is placed at {} {}, where `{}` \
+                                           already is", name, pos.align(), pos.slot(), other)));
         }
+        taken.push((String::from(name), pos));
+    }
+    Ok(())
+}
 
-        // Date.
-        bar.register(Position::right(), &sys.datetime);
+/// A slot whose text is set over the control socket.
+struct NamedSlot {
+    name: String,
+    pos: Position,
+    text: String,
+}
 
-        // Battery.
-        bar.register(Position::left(), &sys.bat);
+/// Builds the slots described by `conf`, keeping the text of any that are in `old`.
+fn build_slots(conf: &Config, old: &[NamedSlot]) -> Vec<NamedSlot> {
+    let slots = match conf.slots {
+        Some(ref slots) => slots,
+        None => return Vec::new(),
+    };
+    slots.iter().map(|slot| {
+        let text = old.iter()
+            .find(|o| o.name == slot.name)
+            .map(|o| o.text.clone())
+            .or_else(|| slot.text.clone())
+            .unwrap_or(String::new());
+        NamedSlot {
+            name: slot.name.clone(),
+            // Already checked by `Config::validate`.
+            pos: Position::new(slot.align.parse().unwrap_or(Align::Left), slot.index),
+            text: text,
+        }
+    }).collect()
+}
 
-        // Cpu.
-        bar.register(Position::new(Align::Left, 1), &sys.cpu);
+/// A provider fed by a supervised child.
+struct Spawned<P> {
+    provider: P,
+    child: ChildId,
+    src: Token,
+}
 
-        // Window manager.
-        bar.register(Position::center(), &wm);
+/// Spawns `cmd` under supervision, routing its output to `provider`.
+fn spawn<P>(ev: &mut EventLoop,
+            sup: &mut Supervisor,
+            name: &str,
+            cmd: Command,
+            provider: P)
+    -> Result<Spawned<P>>
+{
+    // Each child gets its own pipe, so every line can be traced back to whoever wrote it.
+    let (rd, wr) = try!(pipe::pipe());
+    let child = try!(sup.spawn(name, cmd, &wr));
+    let src = try!(ev.add_pipe(rd));
 
-        try!(bar.flush());
+    Ok(Spawned {
+        provider: provider,
+        child: child,
+        src: src,
+    })
+}
+
+/// Spawns every script described by `conf`.
+fn spawn_scripts(ev: &mut EventLoop, sup: &mut Supervisor, conf: &Config)
+    -> Result<Vec<Spawned<Script>>>
+{
+    let mut scripts = Vec::new();
+    if let Some(ref sconfs) = conf.scripts {
+        for sconf in sconfs {
+            let script = try!(Script::new(sconf));
+            let cmd = script.command();
+            scripts.push(try!(spawn(ev, sup, &sconf.name, cmd, script)));
+        }
     }
-    Ok(())
+    Ok(scripts)
+}
+
+/// Creates every exec module described by `conf`.
+fn build_execs(ev: &mut EventLoop, conf: &Config) -> Result<Vec<Exec>> {
+    let mut execs = Vec::new();
+    if let Some(ref econfs) = conf.execs {
+        for econf in econfs {
+            execs.push(try!(Exec::new(econf, ev)));
+        }
+    }
+    Ok(execs)
+}
+
+/// Creates every tail module described by `conf`.
+fn build_tails(ev: &mut EventLoop, conf: &Config) -> Result<Vec<Tail>> {
+    let mut tails = Vec::new();
+    if let Some(ref tconfs) = conf.tails {
+        for tconf in tconfs {
+            tails.push(try!(Tail::new(tconf, ev)));
+        }
+    }
+    Ok(tails)
+}
+
+/// Creates every backlight module described by `conf`.
+fn build_backlights(ev: &mut EventLoop, conf: &Config) -> Result<Vec<Backlight>> {
+    let mut backlights = Vec::new();
+    for bconf in conf.backlights.as_ref().map(|b| &**b).unwrap_or(&[]) {
+        backlights.push(try!(Backlight::new(bconf, ev)));
+    }
+    Ok(backlights)
+}
+
+/// A graph of a series kept by `System`.
+struct Graph {
+    name: String,
+    series: String,
+    pos: Position,
+    width: usize,
+    max: Option<f64>,
+}
+
+/// Creates every sparkline described by `conf`.
+fn build_graphs(conf: &Config) -> Result<Vec<Graph>> {
+    let mut graphs = Vec::new();
+    for sconf in conf.sparklines.as_ref().map(|s| &**s).unwrap_or(&[]) {
+        let align: Align = try!(sconf.align.parse());
+        graphs.push(Graph {
+            name: sconf.name.clone(),
+            series: sconf.series.clone(),
+            pos: Position::new(align, sconf.index),
+            width: sconf.width,
+            max: sconf.max,
+        });
+    }
+    Ok(graphs)
+}
+
+/// Creates every alert described by `conf`.
+fn build_alerts(conf: &Config) -> Result<Vec<Alert>> {
+    let mut alerts = Vec::new();
+    for aconf in conf.alerts.as_ref().map(|a| &**a).unwrap_or(&[]) {
+        alerts.push(try!(Alert::new(aconf)));
+    }
+    Ok(alerts)
+}
+
+/// Creates every hook described by `conf`.
+fn build_hooks(conf: &Config) -> Result<Vec<Hook>> {
+    let mut hooks = Vec::new();
+    for hconf in conf.hook.as_ref().map(|h| &**h).unwrap_or(&[]) {
+        hooks.push(try!(Hook::new(hconf)));
+    }
+    Ok(hooks)
+}
+
+/// A module provided by a plugin, optionally fed by a command.
+struct Plugged {
+    module: plugin::Module,
+    pos: Position,
+    feed: Option<Spawned<()>>,
+}
+
+impl Plugged {
+    fn is_fed_by(&self, src: Token) -> bool {
+        self.feed.as_ref().map_or(false, |f| f.src == src)
+    }
+
+    fn is_run_by(&self, child: ChildId) -> bool {
+        self.feed.as_ref().map_or(false, |f| f.child == child)
+    }
+}
+
+/// Loads the plugins described by `conf`, and places the modules it asks for.
+fn load_plugins(ev: &mut EventLoop, sup: &mut Supervisor, conf: &Config) -> Result<Vec<Plugged>> {
+    let pconf = match conf.plugins {
+        Some(ref pconf) => pconf,
+        None => return Ok(Vec::new()),
+    };
+    let mut available = try!(plugin::load_dir(Path::new(&pconf.dir)));
+
+    let mut plugged = Vec::new();
+    for mconf in &pconf.modules {
+        let module = match available.iter().position(|m| m.name() == mconf.name) {
+            Some(idx) => available.swap_remove(idx),
+            None => {
+                bar::log_error(&format!("no plugin provides module `{}`", mconf.name));
+                continue;
+            },
+        };
+        let feed = match mconf.command {
+            Some(ref command) => {
+                let mut cmd = Command::new("sh");
+                cmd.arg("-c")
+                   .arg(command)
+                   .stdin(Stdio::null())
+                   .stderr(Stdio::inherit());
+                Some(try!(spawn(ev, sup, &mconf.name, cmd, ())))
+            },
+            None => None,
+        };
+        plugged.push(Plugged {
+            module: module,
+            pos: Position::new(try!(mconf.align.parse()), mconf.index),
+            feed: feed,
+        });
+    }
+    Ok(plugged)
+}
+
+/// Minimum time between frames, in milliseconds, unless the config says otherwise.
+const FRAME_INTERVAL: u64 = 16;
+
+/// Seconds between updates of the date and the battery, unless the config says otherwise.
+const DATE_INTERVAL: u64 = 1;
+const BATTERY_INTERVAL: u64 = 1;
+
+/// Milliseconds between steps of a scrolling title, unless the config says otherwise.
+const MARQUEE_STEP: u64 = 200;
+
+/// Budget of a format script, in steps, unless the config says otherwise.
+const FORMAT_STEPS: usize = 1000;
+
+/// A script deciding how a module is displayed.
+struct Formatted {
+    module: String,
+    program: Program,
+    max_steps: usize,
+    /// The last error, so a broken script is only reported once.
+    error: Option<String>,
+}
+
+fn build_formats(conf: &Config) -> Vec<Formatted> {
+    let formats = match conf.formats {
+        Some(ref formats) => formats,
+        None => return Vec::new(),
+    };
+    // Scripts that don't parse have already been refused by `Config::validate`.
+    formats.iter().filter_map(|f| f.script.parse().ok().map(|program| {
+        Formatted {
+            module: f.module.clone(),
+            program: program,
+            max_steps: f.max_steps.unwrap_or(FORMAT_STEPS),
+            error: None,
+        }
+    })).collect()
+}
+
+struct App {
+    conf: Config,
+    conf_path: PathBuf,
+    conf_watch: Token,
+    ev: EventLoop,
+    sup: Supervisor,
+    bar: Bar,
+    lemonbar: ChildId,
+    clicks: Token,
+    sys: Spawned<System>,
+    wm: Spawned<WindowManager>,
+    title: Option<Spawned<WindowTitle>>,
+    /// Drives the scrolling of the title, if it scrolls.
+    scroll: Option<Token>,
+    slots: Vec<NamedSlot>,
+    scripts: Vec<Spawned<Script>>,
+    execs: Vec<Exec>,
+    tails: Vec<Tail>,
+    backlights: Vec<Backlight>,
+    mpd: Option<Mpd>,
+    graphs: Vec<Graph>,
+    alerts: Vec<Alert>,
+    hooks: Vec<Hook>,
+    plugins: Vec<Plugged>,
+    formats: Vec<Formatted>,
+    control: Option<Server>,
+    /// Drives animations and anything else that changes every second.
+    clock: Token,
+    /// Poll the date and the battery.
+    date_timer: Token,
+    bat_timer: Token,
+    /// When each element last received new data.
+    updated: BTreeMap<Position, Instant>,
+    /// When hooks were last checked against the data that changed.
+    hooks_checked: Instant,
+    /// Pending restarts, keyed by the timer that triggers them.
+    restarts: Vec<(Token, ChildId)>,
+    dirty: bool,
+    last_render: Option<Instant>,
+    /// Armed when an update arrives too soon after the last frame.
+    frame_timer: Option<Token>,
+}
+
+impl App {
+    fn new(conf_path: PathBuf, conf: Config) -> Result<App> {
+        try!(check_positions(&conf));
+        let mut ev = try!(EventLoop::new());
+        let mut sup = Supervisor::new();
+
+        // Must be blocked before anything is spawned, or an early exit could go unnoticed.
+        try!(ev.add_signals(&[libc::SIGCHLD,
+                              libc::SIGTERM,
+                              libc::SIGINT,
+                              libc::SIGHUP,
+                              sys::SIGUSR1]));
+
+        let mut bar = Bar::new();
+        let (rd, wr) = try!(pipe::pipe());
+        let lemonbar = try!(sup.spawn("lemonbar", Bar::command(&gen_args(&conf)), &wr));
+        let clicks = try!(ev.add_pipe(rd));
+        bar.set_output(try!(sup.take_stdin(lemonbar)
+                            .ok_or(Error::new("failed to grab `lemonbar` stdin"))));
+
+        let mut system = System::new();
+        system.bat.set_style(battery_style(&conf));
+        system.bat.refresh();
+        let sys = try!(spawn(&mut ev, &mut sup, "conky", System::command(), system));
+        let wm = try!(spawn(&mut ev, &mut sup, "bspc", WindowManager::command(),
+                            WindowManager::new()));
+        let title = match conf.title {
+            Some(ref tconf) => {
+                let title = try!(WindowTitle::new(tconf));
+                let cmd = title.command();
+                Some(try!(spawn(&mut ev, &mut sup, "title", cmd, title)))
+            },
+            None => None,
+        };
+
+        let scroll = try!(scroll_timer(&mut ev, &conf));
+        let slots = build_slots(&conf, &[]);
+        let scripts = try!(spawn_scripts(&mut ev, &mut sup, &conf));
+        let execs = try!(build_execs(&mut ev, &conf));
+        let tails = try!(build_tails(&mut ev, &conf));
+        let backlights = try!(build_backlights(&mut ev, &conf));
+        let mpd = match conf.mpd {
+            Some(ref mconf) => Some(try!(Mpd::new(mconf, &mut ev))),
+            None => None,
+        };
+        let graphs = try!(build_graphs(&conf));
+        let alerts = try!(build_alerts(&conf));
+        let hooks = try!(build_hooks(&conf));
+        let plugins = try!(load_plugins(&mut ev, &mut sup, &conf));
+        let formats = build_formats(&conf);
+
+        // Not being remote controllable isn't worth dying over.
+        let control = match Server::bind(&control::socket_path(), &mut ev) {
+            Ok(server) => Some(server),
+            Err(err) => {
+                bar::log_error(&err);
+                None
+            },
+        };
+
+        let clock = try!(ev.add_timer(Duration::from_secs(1)));
+        let (date_timer, bat_timer) = try!(poll_timers(&mut ev, &conf));
+
+        // Watch the directory rather than the file itself, since editors tend to replace files
+        // wholesale when saving.
+        let conf_dir = match conf_path.parent() {
+            Some(dir) if dir != Path::new("") => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        let conf_watch = try!(ev.add_watch(&conf_dir, Watch::Dir));
+
+        let mut app = App {
+            conf: conf,
+            conf_path: conf_path,
+            conf_watch: conf_watch,
+            ev: ev,
+            sup: sup,
+            bar: bar,
+            lemonbar: lemonbar,
+            clicks: clicks,
+            sys: sys,
+            wm: wm,
+            title: title,
+            scroll: scroll,
+            slots: slots,
+            scripts: scripts,
+            execs: execs,
+            tails: tails,
+            backlights: backlights,
+            mpd: mpd,
+            graphs: graphs,
+            alerts: alerts,
+            hooks: hooks,
+            plugins: plugins,
+            formats: formats,
+            control: control,
+            clock: clock,
+            date_timer: date_timer,
+            bat_timer: bat_timer,
+            updated: BTreeMap::new(),
+            hooks_checked: Instant::now(),
+            restarts: Vec::new(),
+            // Render once up front rather than waiting for the first event.
+            dirty: true,
+            last_render: None,
+            frame_timer: None,
+        };
+        app.apply_layout();
+        app.check_hooks();
+        Ok(app)
+    }
+
+    /// Runs until a terminating signal arrives, returning the exit status.
+    fn run(&mut self) -> Result<i32> {
+        self.sys.provider.datetime.update();
+
+        loop {
+            if self.dirty && !self.ev.has_pending() && self.frame_timer.is_none() {
+                try!(self.render_or_defer());
+            }
+
+            let event = try!(self.ev.next());
+            if let Some(code) = try!(self.handle(event)) {
+                return Ok(code);
+            }
+            self.run_hooks();
+        }
+    }
+
+    /// Renders now if enough time has passed since the last frame, or arms a timer to render
+    /// once it has, so bursts of updates are drawn as one frame.
+    fn render_or_defer(&mut self) -> Result<()> {
+        let interval = Duration::from_millis(self.conf.frame_interval.unwrap_or(FRAME_INTERVAL));
+        let elapsed = self.last_render.map_or(interval, |t| t.elapsed());
+        if elapsed >= interval {
+            self.render();
+        } else {
+            self.frame_timer = Some(try!(self.ev.add_oneshot(interval - elapsed)));
+        }
+        Ok(())
+    }
+
+    fn render(&mut self) {
+        self.register();
+
+        // If `lemonbar` died it will be restarted, so this isn't fatal.
+        if let Err(err) = self.bar.flush() {
+            bar::log_error(&err);
+        }
+        self.dirty = false;
+        self.last_render = Some(Instant::now());
+    }
+
+    /// Hands the current text of every module to the bar, without drawing a frame.
+    fn register(&mut self) {
+        self.bar.register(date_pos(), &self.sys.provider.datetime);
+        self.bar.register(bat_pos(), &self.sys.provider.bat);
+        self.bar.register(cpu_pos(), &self.sys.provider.cpu);
+        self.bar.register(wm_pos(), &self.wm.provider);
+        if let Some(ref title) = self.title {
+            self.bar.register(title_pos(), &title.provider);
+        }
+        for slot in &self.slots {
+            // Slots are set from outside, so they don't get to inject formatting.
+            self.bar.register(slot.pos, &*text::escape(&slot.text));
+        }
+        for script in &self.scripts {
+            self.bar.register(script.provider.position(), &script.provider);
+        }
+        for exec in &self.execs {
+            self.bar.register(exec.position(), exec);
+        }
+        for tail in &self.tails {
+            self.bar.register(tail.position(), tail);
+        }
+        for backlight in &self.backlights {
+            self.bar.register(backlight.position(), backlight);
+        }
+        if let Some(ref mpd) = self.mpd {
+            self.bar.register(mpd.position(), mpd);
+        }
+        for graph in &self.graphs {
+            if let Some(history) = self.sys.provider.history(&graph.series) {
+                self.bar.register(graph.pos, &Sparkline::new(history, graph.width, graph.max));
+            }
+        }
+        for plugged in &self.plugins {
+            self.bar.register(plugged.pos, &plugged.module);
+        }
+        self.apply_formats();
+    }
+
+    /// Replaces the text of every module that has a format script with the script's output.
+    ///
+    /// A module whose script fails keeps its own text.
+    fn apply_formats(&mut self) {
+        let mut results = Vec::new();
+        for (i, format) in self.formats.iter().enumerate() {
+            let res = match self.module(&format.module) {
+                Some(pos) => {
+                    format.program.eval(&self.data(&format.module), format.max_steps)
+                        .map(|out| (pos, out))
+                },
+                None => Err(Error::new("no such module")),
+            };
+            results.push((i, res));
+        }
+
+        for (i, res) in results {
+            let format = &mut self.formats[i];
+            match res {
+                Ok((pos, out)) => {
+                    self.bar.register(pos, &out);
+                    format.error = None;
+                },
+                Err(err) => {
+                    let msg = format!("format of `{}`: {}", format.module, err);
+                    if format.error.as_ref() != Some(&msg) {
+                        bar::log_error(&msg);
+                    }
+                    format.error = Some(msg);
+                },
+            }
+        }
+    }
+
+    /// Handles a single event, returning an exit status if it's time to quit.
+    fn handle(&mut self, event: Event) -> Result<Option<i32>> {
+        if let Some(ref mut title) = self.title {
+            if title.provider.owns(&event) {
+                if title.provider.handle(event) {
+                    self.updated.insert(title_pos(), Instant::now());
+                    self.dirty = true;
+                }
+                return Ok(None);
+            }
+        }
+        if let Some(exec) = self.execs.iter_mut().find(|e| e.owns(&event)) {
+            if try!(exec.handle(&mut self.ev, event)) {
+                self.updated.insert(exec.position(), Instant::now());
+                self.dirty = true;
+            }
+            return Ok(None);
+        }
+        if let Some(tail) = self.tails.iter_mut().find(|t| t.owns(&event)) {
+            match tail.handle(&mut self.ev, event) {
+                Ok(true) => {
+                    self.updated.insert(tail.position(), Instant::now());
+                    self.dirty = true;
+                },
+                Ok(false) => {},
+                Err(err) => bar::log_error(&err),
+            }
+            return Ok(None);
+        }
+        if let Some(backlight) = self.backlights.iter_mut().find(|b| b.owns(&event)) {
+            let pos = backlight.position();
+            match backlight.handle(event) {
+                Ok(changed) => {
+                    if changed {
+                        self.updated.insert(pos, Instant::now());
+                    }
+                    if changed || self.bar.is_stale(pos) {
+                        self.bar.set_stale(pos, false);
+                        self.dirty = true;
+                    }
+                },
+                Err(err) => {
+                    // The device may be back later; until then, show that the value is old.
+                    if !self.bar.is_stale(pos) {
+                        bar::log_error(&format!("backlight `{}`: {}", backlight.name(), err));
+                    }
+                    self.bar.set_stale(pos, true);
+                    self.dirty = true;
+                },
+            }
+            return Ok(None);
+        }
+        if self.control.as_ref().map_or(false, |c| c.owns(&event)) {
+            let res = match self.control {
+                Some(ref mut server) => server.handle(&mut self.ev, event),
+                None => Ok(None),
+            };
+            // A misbehaving client shouldn't take the whole bar down.
+            let req = match res {
+                Ok(req) => req,
+                Err(err) => {
+                    bar::log_error(&format!("control socket: {}", err));
+                    None
+                },
+            };
+            if let Some((cmd, mut client)) = req {
+                self.command(cmd, &mut client);
+            }
+            return Ok(None);
+        }
+        if let Some(ref mut mpd) = self.mpd {
+            if mpd.owns(&event) {
+                if try!(mpd.handle(&mut self.ev, event)) {
+                    self.updated.insert(mpd.position(), Instant::now());
+                    self.dirty = true;
+                }
+                return Ok(None);
+            }
+        }
+
+        match event {
+            Event::Line(src, line) if src == self.clicks => self.click(&line),
+            Event::Line(src, line) => self.consume(src, &line),
+            Event::Timer(src) if Some(src) == self.frame_timer => {
+                try!(self.ev.remove(src));
+                self.frame_timer = None;
+            },
+            Event::Timer(src) if Some(src) == self.scroll => {
+                if let Some(ref mut title) = self.title {
+                    if title.provider.step() {
+                        self.dirty = true;
+                    }
+                }
+            },
+            Event::Timer(src) if src == self.date_timer => {
+                if self.sys.provider.datetime.update() {
+                    self.updated.insert(date_pos(), Instant::now());
+                    self.dirty = true;
+                }
+            },
+            Event::Timer(src) if src == self.bat_timer => {
+                if self.sys.provider.bat.refresh() {
+                    self.updated.insert(bat_pos(), Instant::now());
+                    self.dirty = true;
+                    self.check_alerts();
+                }
+            },
+            Event::Timer(src) if src == self.clock => {
+                if self.sys.provider.bat.tick() {
+                    self.dirty = true;
+                }
+                if self.mpd.as_ref().map_or(false, |m| m.tick()) {
+                    self.dirty = true;
+                }
+                for plugged in &mut self.plugins {
+                    if plugged.module.tick() {
+                        self.updated.insert(plugged.pos, Instant::now());
+                        self.dirty = true;
+                    }
+                }
+            },
+            Event::Timer(src) => try!(self.restart(src)),
+            Event::Signal(_, libc::SIGCHLD) => try!(self.reap()),
+            Event::Signal(_, sys::SIGUSR1) => self.reload(),
+            Event::Signal(_, sig) if sig == libc::SIGTERM ||
+                                     sig == libc::SIGINT ||
+                                     sig == libc::SIGHUP => {
+                return Ok(Some(self.shutdown(sig)));
+            },
+            Event::Changed(src, ref name) if src == self.conf_watch => {
+                let name = name.as_ref().map(|n| &**n);
+                if name.is_some() && self.conf_path.file_name().and_then(|f| f.to_str()) == name {
+                    self.reload();
+                }
+            },
+            _ => {},
+        }
+        Ok(None)
+    }
+
+    fn consume(&mut self, src: Token, line: &str) {
+        let (child, res) = if src == self.sys.src {
+            (self.sys.child, self.sys.provider.consume(line))
+        } else if src == self.wm.src {
+            (self.wm.child, self.wm.provider.consume(line))
+        } else if self.title.as_ref().map_or(false, |t| t.src == src) {
+            let title = self.title.as_mut().unwrap();
+            let res = title.provider.consume(line);
+            if let Err(err) = title.provider.follow(&mut self.ev) {
+                bar::log_error(&err);
+            }
+            (title.child, res)
+        } else if let Some(script) = self.scripts.iter_mut().find(|s| s.src == src) {
+            (script.child, script.provider.consume(line))
+        } else if let Some(plugged) = self.plugins.iter_mut().find(|p| p.is_fed_by(src)) {
+            (plugged.feed.as_ref().unwrap().child, plugged.module.consume(line))
+        } else {
+            return;
+        };
+
+        // A malformed line shouldn't take the whole bar down.
+        match res {
+            Ok(()) => {
+                let now = Instant::now();
+                // conky feeds several modules, each line only one of them.
+                let positions = if child == self.sys.child {
+                    self.updated_by(line)
+                } else {
+                    self.positions(child)
+                };
+                for pos in positions {
+                    self.updated.insert(pos, now);
+                }
+            },
+            Err(err) => bar::log_error(&err),
+        }
+        if child == self.sys.child {
+            self.check_alerts();
+        }
+        // Providers may show that they errored.
+        self.dirty = true;
+    }
+
+    /// Runs the command of every hook whose module's data changed in a way it cares about.
+    ///
+    /// Only hooks on modules updated since the last check are evaluated. Commands run in the
+    /// background, so a slow one doesn't hold up the bar.
+    fn run_hooks(&mut self) {
+        if self.hooks.is_empty() {
+            return;
+        }
+        let since = self.hooks_checked;
+        self.hooks_checked = Instant::now();
+
+        let modules = self.modules();
+        let mut due = Vec::new();
+        for (i, hook) in self.hooks.iter().enumerate() {
+            let updated = modules.iter()
+                .find(|&&(ref name, _)| name == hook.module())
+                .and_then(|&(_, pos)| self.updated.get(&pos))
+                .map_or(false, |&t| t >= since);
+            if updated {
+                due.push((i, self.data(hook.module())));
+            }
+        }
+        for (i, data) in due {
+            if let Some(mut cmd) = self.hooks[i].check(&data) {
+                if let Err(err) = self.sup.run(&mut cmd) {
+                    bar::log_error(&err);
+                }
+            }
+        }
+    }
+
+    /// Logs every hook on a module that doesn't exist, since it would never fire.
+    fn check_hooks(&self) {
+        for hook in &self.hooks {
+            if self.module(hook.module()).is_none() {
+                bar::log_error(&format!("hook on unknown module `{}`", hook.module()));
+            }
+        }
+    }
+
+    /// Runs the command of every alert whose threshold the system's values just crossed.
+    fn check_alerts(&mut self) {
+        for alert in &mut self.alerts {
+            if let Some(mut cmd) = alert.check(&self.sys.provider) {
+                if let Err(err) = self.sup.run(&mut cmd) {
+                    bar::log_error(&err);
+                }
+            }
+        }
+    }
+
+    /// Runs the command behind a clickable area `lemonbar` reported as clicked.
+    fn click(&mut self, action: &str) {
+        if let Some(backlight) = self.backlights.iter_mut().find(|b| b.owns_action(action)) {
+            match backlight.scroll(action) {
+                Ok(Some(mut cmd)) => {
+                    if let Err(err) = self.sup.run(&mut cmd) {
+                        bar::log_error(&err);
+                    }
+                },
+                Ok(None) => {
+                    self.updated.insert(backlight.position(), Instant::now());
+                    self.dirty = true;
+                },
+                Err(err) => bar::log_error(&err),
+            }
+            return;
+        }
+        if let Some(ref mut mpd) = self.mpd {
+            if mpd.owns_action(action) {
+                if let Err(err) = mpd.click(&mut self.ev, action) {
+                    bar::log_error(&err);
+                }
+                return;
+            }
+        }
+
+        let cmd = self.scripts.iter()
+            .find(|s| s.provider.action() == action)
+            .and_then(|s| s.provider.on_click())
+            .map(String::from);
+        if let Some(cmd) = cmd {
+            let res = self.sup.run(Command::new("sh")
+                                   .arg("-c")
+                                   .arg(&cmd)
+                                   .stdin(Stdio::null())
+                                   .stdout(Stdio::null())
+                                   .stderr(Stdio::inherit()));
+            if let Err(err) = res {
+                bar::log_error(&err);
+            }
+        }
+    }
+
+    fn command(&mut self, cmd: control::Command, client: &mut Client) {
+        match cmd {
+            control::Command::Set(name, text) => {
+                match self.slots.iter_mut().find(|s| s.name == name) {
+                    Some(slot) => {
+                        slot.text = text;
+                        self.updated.insert(slot.pos, Instant::now());
+                    },
+                    None => return client.error(&format!("no such slot `{}`", name)),
+                }
+            },
+            control::Command::Hide(name) => {
+                match self.module(&name) {
+                    Some(pos) => self.bar.set_hidden(pos, true),
+                    None => return client.error(&format!("no such module `{}`", name)),
+                }
+            },
+            control::Command::Show(name) => {
+                match self.module(&name) {
+                    Some(pos) => self.bar.set_hidden(pos, false),
+                    None => return client.error(&format!("no such module `{}`", name)),
+                }
+            },
+            control::Command::Refresh => self.bar.invalidate(),
+            control::Command::Toggle => {
+                let visible = self.bar.is_visible();
+                self.bar.set_visible(!visible);
+            },
+            control::Command::State(true) => {
+                // Make sure the rendered text is current.
+                self.register();
+                client.reply(&self.state().to_string());
+                // Queries leave the bar as it is.
+                return;
+            },
+            control::Command::State(false) => {
+                for (name, pos) in self.modules() {
+                    let vis = if self.bar.is_hidden(pos) { "hidden" } else { "visible" };
+                    client.reply(&format!("{} {} {} {}", name, pos.align(), pos.slot(), vis));
+                }
+                return;
+            },
+        }
+        self.dirty = true;
+    }
+
+    /// Every module and slot that can be addressed by name, with where it is placed.
+    fn modules(&self) -> Vec<(String, Position)> {
+        let mut modules: Vec<_> = module_names().into_iter()
+            .filter(|&(name, _)| name != "title" || self.title.is_some())
+            .map(|(name, pos)| (String::from(name), pos))
+            .collect();
+        modules.extend(self.slots.iter().map(|s| (s.name.clone(), s.pos)));
+        modules.extend(self.scripts.iter()
+                       .map(|s| (String::from(s.provider.name()), s.provider.position())));
+        modules.extend(self.execs.iter().map(|e| (String::from(e.name()), e.position())));
+        modules.extend(self.tails.iter().map(|t| (String::from(t.name()), t.position())));
+        modules.extend(self.backlights.iter()
+                       .map(|b| (String::from(b.name()), b.position())));
+        modules.extend(self.mpd.iter().map(|m| (String::from(m.name()), m.position())));
+        modules.extend(self.graphs.iter().map(|g| (g.name.clone(), g.pos)));
+        modules.extend(self.plugins.iter().map(|p| (String::from(p.module.name()), p.pos)));
+        modules
+    }
+
+    /// Describes every module, for debugging and for other tools to consume.
+    fn state(&self) -> Json {
+        let now = Instant::now();
+        let modules = self.modules().into_iter().map(|(name, pos)| {
+            let age = self.updated.get(&pos).map(|&t| {
+                let age = now.duration_since(t);
+                age.as_secs() as f64 + age.subsec_nanos() as f64 / 1e9
+            });
+
+            let mut obj = BTreeMap::new();
+            obj.insert(String::from("align"), pos.align().to_string().to_json());
+            obj.insert(String::from("slot"), pos.slot().to_json());
+            obj.insert(String::from("visible"), (!self.bar.is_hidden(pos)).to_json());
+            obj.insert(String::from("stale"), self.bar.is_stale(pos).to_json());
+            obj.insert(String::from("text"), self.bar.rendered(pos).to_json());
+            obj.insert(String::from("data"), self.data(&name));
+            obj.insert(String::from("age"), age.to_json());
+            obj.insert(String::from("name"), name.to_json());
+            Json::Object(obj)
+        }).collect();
+        Json::Array(modules)
+    }
+
+    /// The raw values behind a module's text.
+    fn data(&self, name: &str) -> Json {
+        match name {
+            "date" => self.sys.provider.datetime.to_json(),
+            "battery" => self.sys.provider.bat.to_json(),
+            "cpu" => self.sys.provider.cpu.to_json(),
+            "desktops" => self.wm.provider.to_json(),
+            "title" => self.title.as_ref().map_or(Json::Null, |t| t.provider.to_json()),
+            _ => {
+                self.scripts.iter()
+                    .find(|s| s.provider.name() == name)
+                    .map(|s| s.provider.to_json())
+                    .or_else(|| self.execs.iter().find(|e| e.name() == name).map(|e| e.to_json()))
+                    .or_else(|| self.tails.iter().find(|t| t.name() == name).map(|t| t.to_json()))
+                    .or_else(|| {
+                        self.backlights.iter().find(|b| b.name() == name).map(|b| b.to_json())
+                    })
+                    .or_else(|| self.mpd.iter().find(|m| m.name() == name).map(|m| m.to_json()))
+                    .or_else(|| {
+                        self.graphs.iter()
+                            .find(|g| g.name == name)
+                            .and_then(|g| self.sys.provider.history(&g.series))
+                            .map(|h| h.to_json())
+                    })
+                    .unwrap_or(Json::Null)
+            },
+        }
+    }
+
+    fn module(&self, name: &str) -> Option<Position> {
+        self.modules().into_iter().find(|&(ref n, _)| n == name).map(|(_, pos)| pos)
+    }
+
+    /// The elements a line of conky's output updates, going by its key.
+    fn updated_by(&self, line: &str) -> Vec<Position> {
+        let key = line.split('=').next().map_or("", |k| k.trim());
+        let (module, series): (Option<Position>, &[&str]) = match key {
+            "BAT_TIME" | "BAT_STATUS" => (Some(bat_pos()), &[]),
+            "TIME" | "DATE" => (Some(date_pos()), &[]),
+            "TEMP" | "CPU_FREQ" => (Some(cpu_pos()), &[]),
+            "CPU" => (Some(cpu_pos()), &["cpu", "cpu0", "cpu1", "cpu2", "cpu3"]),
+            "MEM" => (None, &["mem"]),
+            "NET" => (None, &["net_down", "net_up"]),
+            _ => (None, &[]),
+        };
+        let mut positions: Vec<Position> = module.into_iter().collect();
+        positions.extend(self.graphs.iter()
+                         .filter(|g| series.contains(&&*g.series))
+                         .map(|g| g.pos));
+        positions
+    }
+
+    /// The elements fed by `child`, so they can be marked stale while it is down.
+    fn positions(&self, child: ChildId) -> Vec<Position> {
+        if child == self.sys.child {
+            let mut positions = vec![date_pos(), bat_pos(), cpu_pos()];
+            positions.extend(self.graphs.iter().map(|g| g.pos));
+            positions
+        } else if child == self.wm.child {
+            vec![wm_pos()]
+        } else if self.title.as_ref().map_or(false, |t| t.child == child) {
+            vec![title_pos()]
+        } else if let Some(script) = self.scripts.iter().find(|s| s.child == child) {
+            vec![script.provider.position()]
+        } else if let Some(plugged) = self.plugins.iter().find(|p| p.is_run_by(child)) {
+            vec![plugged.pos]
+        } else {
+            Vec::new()
+        }
+    }
+
+    fn reap(&mut self) -> Result<()> {
+        for exec in &mut self.execs {
+            if try!(exec.reap(&mut self.ev)) {
+                self.updated.insert(exec.position(), Instant::now());
+                self.dirty = true;
+            }
+        }
+
+        for exit in self.sup.reap() {
+            bar::log_error(&exit);
+            let timer = try!(self.ev.add_oneshot(exit.backoff));
+            self.restarts.push((timer, exit.id));
+
+            for pos in self.positions(exit.id) {
+                self.bar.set_stale(pos, true);
+            }
+            self.dirty = true;
+        }
+        Ok(())
+    }
+
+    fn restart(&mut self, timer: Token) -> Result<()> {
+        let idx = match self.restarts.iter().position(|&(t, _)| t == timer) {
+            Some(idx) => idx,
+            None => return Ok(()),
+        };
+        let (_, child) = self.restarts.remove(idx);
+        try!(self.ev.remove(timer));
+
+        if let Err(err) = self.sup.restart(child) {
+            bar::log_error(&err);
+            let timer = try!(self.ev.add_oneshot(self.sup.backoff(child)));
+            self.restarts.push((timer, child));
+            return Ok(());
+        }
+
+        if child == self.lemonbar {
+            if let Some(stdin) = self.sup.take_stdin(child) {
+                self.bar.set_output(stdin);
+            }
+        }
+        for pos in self.positions(child) {
+            self.bar.set_stale(pos, false);
+        }
+        self.dirty = true;
+        Ok(())
+    }
+
+    /// Re-reads the config and applies whatever changed.
+    ///
+    /// On error, the parts applied so far are kept and recorded as such, so the next reload
+    /// retries only the rest.
+    fn reload(&mut self) {
+        if let Err(err) = self.try_reload() {
+            bar::log_error(&err);
+        }
+    }
+
+    fn try_reload(&mut self) -> Result<()> {
+        let conf = try!(Config::from_path(&self.conf_path));
+        try!(check_positions(&conf));
+        if conf == self.conf {
+            return Ok(());
+        }
+
+        // Restarting `lemonbar` makes the bar flicker, so only do it when it has to be.
+        let args = gen_args(&conf);
+        if args != gen_args(&self.conf) {
+            try!(self.sup.respawn(self.lemonbar, Bar::command(&args)));
+            let stdin = try!(self.sup.take_stdin(self.lemonbar)
+                             .ok_or(Error::new("failed to grab `lemonbar` stdin")));
+            self.bar.set_output(stdin);
+            self.conf.fonts = conf.fonts.clone();
+            self.conf.geom = conf.geom.clone();
+            self.conf.color = conf.color.clone();
+        }
+
+        if conf.title != self.conf.title {
+            if let Some(mut old) = self.title.take() {
+                self.sup.stop(old.child);
+                try!(old.provider.stop(&mut self.ev));
+                try!(self.ev.remove(old.src));
+                self.bar.deregister(title_pos());
+                self.bar.set_stale(title_pos(), false);
+                self.updated.remove(&title_pos());
+            }
+            if let Some(ref tconf) = conf.title {
+                let title = try!(WindowTitle::new(tconf));
+                let cmd = title.command();
+                self.title = Some(try!(spawn(&mut self.ev, &mut self.sup, "title", cmd, title)));
+            }
+            if let Some(scroll) = self.scroll.take() {
+                try!(self.ev.remove(scroll));
+            }
+            self.scroll = try!(scroll_timer(&mut self.ev, &conf));
+            self.conf.title = conf.title.clone();
+        }
+
+        if conf.scripts != self.conf.scripts {
+            for old in self.scripts.drain(..) {
+                let pos = old.provider.position();
+                self.sup.stop(old.child);
+                try!(self.ev.remove(old.src));
+                self.bar.deregister(pos);
+                self.bar.set_stale(pos, false);
+                self.updated.remove(&pos);
+            }
+            self.scripts = try!(spawn_scripts(&mut self.ev, &mut self.sup, &conf));
+            self.conf.scripts = conf.scripts.clone();
+        }
+
+        if conf.execs != self.conf.execs {
+            for mut old in self.execs.drain(..) {
+                try!(old.stop(&mut self.ev));
+                self.bar.deregister(old.position());
+                self.updated.remove(&old.position());
+            }
+            self.execs = try!(build_execs(&mut self.ev, &conf));
+            self.conf.execs = conf.execs.clone();
+        }
+
+        if conf.tails != self.conf.tails {
+            for mut old in self.tails.drain(..) {
+                try!(old.stop(&mut self.ev));
+                self.bar.deregister(old.position());
+                self.updated.remove(&old.position());
+            }
+            self.tails = try!(build_tails(&mut self.ev, &conf));
+            self.conf.tails = conf.tails.clone();
+        }
+
+        if conf.backlights != self.conf.backlights {
+            for mut old in self.backlights.drain(..) {
+                try!(old.stop(&mut self.ev));
+                self.bar.deregister(old.position());
+                self.bar.set_stale(old.position(), false);
+                self.updated.remove(&old.position());
+            }
+            self.backlights = try!(build_backlights(&mut self.ev, &conf));
+            self.conf.backlights = conf.backlights.clone();
+        }
+
+        if conf.mpd != self.conf.mpd {
+            if let Some(mut old) = self.mpd.take() {
+                try!(old.stop(&mut self.ev));
+                self.bar.deregister(old.position());
+                self.updated.remove(&old.position());
+            }
+            if let Some(ref mconf) = conf.mpd {
+                self.mpd = Some(try!(Mpd::new(mconf, &mut self.ev)));
+            }
+            self.conf.mpd = conf.mpd.clone();
+        }
+
+        if conf.plugins != self.conf.plugins {
+            for old in self.plugins.drain(..) {
+                if let Some(feed) = old.feed {
+                    self.sup.stop(feed.child);
+                    try!(self.ev.remove(feed.src));
+                }
+                self.bar.deregister(old.pos);
+                self.bar.set_stale(old.pos, false);
+                self.updated.remove(&old.pos);
+            }
+            self.plugins = try!(load_plugins(&mut self.ev, &mut self.sup, &conf));
+            self.conf.plugins = conf.plugins.clone();
+        }
+
+        if conf.formats != self.conf.formats {
+            self.formats = build_formats(&conf);
+            self.conf.formats = conf.formats.clone();
+        }
+
+        if conf.battery != self.conf.battery {
+            self.sys.provider.bat.set_style(battery_style(&conf));
+            self.conf.battery = conf.battery.clone();
+        }
+
+        if conf.intervals != self.conf.intervals {
+            try!(self.ev.remove(self.date_timer));
+            try!(self.ev.remove(self.bat_timer));
+            let (date_timer, bat_timer) = try!(poll_timers(&mut self.ev, &conf));
+            self.date_timer = date_timer;
+            self.bat_timer = bat_timer;
+            self.conf.intervals = conf.intervals.clone();
+        }
+
+        if conf.hook != self.conf.hook {
+            self.hooks = try!(build_hooks(&conf));
+            self.conf.hook = conf.hook.clone();
+        }
+
+        if conf.alerts != self.conf.alerts {
+            self.alerts = try!(build_alerts(&conf));
+            self.conf.alerts = conf.alerts.clone();
+        }
+
+        if conf.sparklines != self.conf.sparklines {
+            for graph in &self.graphs {
+                self.bar.deregister(graph.pos);
+                self.bar.set_stale(graph.pos, false);
+            }
+            self.graphs = try!(build_graphs(&conf));
+            self.conf.sparklines = conf.sparklines.clone();
+        }
+
+        if conf.slots != self.conf.slots {
+            for slot in &self.slots {
+                self.bar.deregister(slot.pos);
+                self.updated.remove(&slot.pos);
+            }
+            self.slots = build_slots(&conf, &self.slots);
+            self.conf.slots = conf.slots.clone();
+        }
+
+        self.conf = conf;
+        self.apply_layout();
+        self.check_hooks();
+        self.dirty = true;
+        Ok(())
+    }
+
+    /// Tells the bar how to decorate modules, how much room it has, and which modules to give up
+    /// first when it runs out.
+    fn apply_layout(&mut self) {
+        self.bar.set_decorations(decorations(&self.conf));
+
+        let layout = self.conf.layout.as_ref().map(|layout| {
+            Layout {
+                width: self.conf.geom.size[0],
+                char_widths: layout.char_widths.clone(),
+            }
+        });
+        self.bar.set_layout(layout);
+
+        self.bar.clear_priorities();
+        let priorities = self.conf.layout.as_ref().and_then(|l| l.priorities.clone());
+        for (name, priority) in priorities.unwrap_or(BTreeMap::new()) {
+            match self.module(&name) {
+                Some(pos) => self.bar.set_priority(pos, priority),
+                None => bar::log_error(&format!("priority of unknown module `{}`", name)),
+            }
+        }
+    }
+
+    /// Blanks the bar and terminates every child, returning the exit status.
+    fn shutdown(&mut self, sig: libc::c_int) -> i32 {
+        if let Err(err) = self.bar.clear() {
+            bar::log_error(&err);
+        }
+        self.sup.shutdown();
+        if let Some(ref mut title) = self.title {
+            if let Err(err) = title.provider.stop(&mut self.ev) {
+                bar::log_error(&err);
+            }
+        }
+        for exec in &mut self.execs {
+            if let Err(err) = exec.stop(&mut self.ev) {
+                bar::log_error(&err);
+            }
+        }
+        for tail in &mut self.tails {
+            if let Err(err) = tail.stop(&mut self.ev) {
+                bar::log_error(&err);
+            }
+        }
+        // The shell convention for processes terminated by a signal.
+        128 + sig
+    }
+}
+
+fn battery_style(conf: &Config) -> BatteryStyle {
+    let mut style = BatteryStyle::default();
+    let bconf = match conf.battery {
+        Some(ref bconf) => bconf,
+        None => return style,
+    };
+
+    if let Some(ref points) = bconf.breakpoints {
+        style.breakpoints = [points[0], points[1], points[2]];
+    }
+    style.animate = bconf.animate.unwrap_or(false);
+    if let Some(ref icons) = bconf.icons {
+        // Codepoints are checked when the config is loaded.
+        let remap = |icon: &mut char, cp: Option<u32>| {
+            if let Some(c) = cp.and_then(char::from_u32) {
+                *icon = c;
+            }
+        };
+        remap(&mut style.empty, icons.empty);
+        remap(&mut style.low, icons.low);
+        remap(&mut style.half, icons.half);
+        remap(&mut style.full, icons.full);
+        remap(&mut style.charging, icons.charging);
+    }
+    style
+}
+
+/// Adds the timers polling the date and the battery.
+fn poll_timers(ev: &mut EventLoop, conf: &Config) -> Result<(Token, Token)> {
+    let intervals = conf.intervals.as_ref();
+    let date = intervals.and_then(|i| i.date).unwrap_or(DATE_INTERVAL);
+    let battery = intervals.and_then(|i| i.battery).unwrap_or(BATTERY_INTERVAL);
+    Ok((try!(ev.add_timer(Duration::from_secs(date))),
+        try!(ev.add_timer(Duration::from_secs(battery)))))
+}
+
+/// Adds the timer scrolling the title, if the title scrolls.
+fn scroll_timer(ev: &mut EventLoop, conf: &Config) -> Result<Option<Token>> {
+    match conf.title.as_ref().and_then(|t| t.marquee.as_ref()) {
+        Some(marquee) => {
+            let step = Duration::from_millis(marquee.step.unwrap_or(MARQUEE_STEP));
+            Ok(Some(try!(ev.add_timer(step))))
+        },
+        None => Ok(None),
+    }
+}
+
+fn decorations(conf: &Config) -> Decorations {
+    let mut decorations = Decorations::default();
+    decorations.background = conf.color.bg.clone();
+    let dconf = match conf.decorations {
+        Some(ref dconf) => dconf,
+        None => return decorations,
+    };
+
+    decorations.separator = match dconf.separator {
+        Some(ref sep) => Separator::Text(sep.clone()),
+        None if dconf.arrows.unwrap_or(false) => Separator::Arrows,
+        None => Separator::None,
+    };
+    let ends = |section: &Option<SectionEnds>| {
+        section.as_ref().map_or(Ends::default(), |s| {
+            Ends {
+                start: s.start.clone().unwrap_or(String::new()),
+                end: s.end.clone().unwrap_or(String::new()),
+            }
+        })
+    };
+    decorations.left = ends(&dconf.left);
+    decorations.center = ends(&dconf.center);
+    decorations.right = ends(&dconf.right);
+    decorations
 }
 
 fn gen_args(conf: &Config) -> Vec<String> {